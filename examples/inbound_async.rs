@@ -0,0 +1,33 @@
+use std::{net::SocketAddr, str::FromStr as _, time::Duration};
+
+use clap::Parser;
+use p0f_rs::AsyncP0f;
+use tokio::net::TcpListener;
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    #[arg(short, long, default_value = "127.0.0.1:6666")]
+    address: String,
+
+    #[arg(short, long)]
+    socket: String,
+}
+
+#[tokio::main]
+async fn main() -> eyre::Result<()> {
+    let args = Args::parse();
+
+    let mut p0f: AsyncP0f = AsyncP0f::new(args.socket).await?;
+    let listener = TcpListener::bind(SocketAddr::from_str(&args.address)?).await?;
+
+    println!("waiting for connection");
+    let (_, addr) = listener.accept().await?;
+    println!("connection from {}", addr);
+
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let response = p0f.query(addr.ip()).await?.unwrap();
+    println!("{:#?}", response);
+
+    Ok(())
+}
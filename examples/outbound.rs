@@ -24,7 +24,7 @@ fn main() -> eyre::Result<()> {
     let address = SocketAddr::from_str(&args.address)?;
 
     let mut p0f: P0f = P0f::new(args.socket)?;
-    let _ = TcpStream::connect(&address)?;
+    let _ = TcpStream::connect(address)?;
 
     thread::sleep(Duration::from_secs(1));
     let response = p0f.query(address.ip())?.unwrap();
@@ -0,0 +1,210 @@
+use std::{
+    io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::UnixStream,
+};
+
+use crate::{
+    protocol::{self, Request, ResponseLayout, PROBE_SIZE, RESPONSE_SIZE_MTU},
+    DecodeMode, Error, Response,
+};
+
+/// Queries are cancel safe: a query dropped before its response has been
+/// read in full leaves the connection out of step, so the next one starts
+/// over on a new connection.
+pub struct AsyncP0f {
+    path: PathBuf,
+    stream: Option<UnixStream>,
+    // Set from writing a request until its response has been read in full.
+    in_flight: bool,
+    decode_mode: DecodeMode,
+    layout: ResponseLayout,
+    resolved_layout: Option<ResponseLayout>,
+}
+
+impl AsyncP0f {
+    pub async fn new<T: AsRef<Path>>(path: T) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let socket = UnixStream::connect(&path).await?;
+
+        Ok(AsyncP0f {
+            path,
+            stream: Some(socket),
+            in_flight: false,
+            decode_mode: DecodeMode::default(),
+            layout: ResponseLayout::default(),
+            resolved_layout: None,
+        })
+    }
+
+    pub fn with_decode_mode(mut self, decode_mode: DecodeMode) -> Self {
+        self.decode_mode = decode_mode;
+        self
+    }
+
+    /// [`ResponseLayout::Auto`] is resolved by the first query on every
+    /// connection.
+    pub fn with_layout(mut self, layout: ResponseLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The layout in use, or [`ResponseLayout::Auto`] if it hasn't been
+    /// probed yet.
+    pub fn layout(&self) -> ResponseLayout {
+        self.resolved_layout.unwrap_or(self.layout)
+    }
+
+    pub async fn query<T: Into<IpAddr>>(&mut self, address: T) -> Result<Option<Response>, Error> {
+        let result = self.try_query(address.into()).await;
+        if let Err(err) = &result {
+            if err.breaks_connection() {
+                self.disconnect();
+            }
+        }
+
+        result
+    }
+
+    async fn try_query(&mut self, address: IpAddr) -> Result<Option<Response>, Error> {
+        let layout = self.resolve_layout().await?;
+        let stream = self.begin().await?;
+
+        stream.write_all(&Request::new(address).encode()).await?;
+        let mut response = [0; RESPONSE_SIZE_MTU];
+        let response = &mut response[..layout.size().unwrap_or(RESPONSE_SIZE_MTU)];
+        stream.read_exact(response).await?;
+        self.in_flight = false;
+
+        protocol::decode_response(response, layout, self.decode_mode)
+    }
+
+    /// The connection to write the next request to, marked as in flight
+    /// until the caller has read the response. One still marked is dropped
+    /// for a new one.
+    async fn begin(&mut self) -> Result<&mut UnixStream, Error> {
+        if self.in_flight {
+            self.disconnect();
+        }
+
+        let stream = match self.stream.take() {
+            Some(stream) => stream,
+            None => UnixStream::connect(&self.path).await?,
+        };
+        self.in_flight = true;
+
+        Ok(self.stream.insert(stream))
+    }
+
+    fn disconnect(&mut self) {
+        self.stream = None;
+        self.in_flight = false;
+        self.resolved_layout = None;
+    }
+
+    /// Probes the layout over the connection it is then used for. Both probe
+    /// responses have to be read before the probe counts as done, so one cut
+    /// short in between gets the connection dropped like a query would.
+    async fn resolve_layout(&mut self) -> Result<ResponseLayout, Error> {
+        if self.in_flight {
+            self.disconnect();
+        }
+        if let Some(layout) = self.resolved_layout {
+            return Ok(layout);
+        }
+        if self.layout != ResponseLayout::Auto {
+            return Ok(self.layout);
+        }
+        let stream = self.begin().await?;
+
+        stream.write_all(&protocol::probe_request()).await?;
+        let mut probe = [0; PROBE_SIZE];
+        stream.read_exact(&mut probe).await?;
+        let layout = ResponseLayout::from_probe(&probe)?;
+
+        // Throw away whatever is left of both probe responses.
+        let mut rest = [0; 2 * RESPONSE_SIZE_MTU - PROBE_SIZE];
+        let size = layout.size().unwrap_or(RESPONSE_SIZE_MTU);
+        stream
+            .read_exact(&mut rest[..2 * size - PROBE_SIZE])
+            .await?;
+        self.in_flight = false;
+
+        Ok(*self.resolved_layout.insert(layout))
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use std::{net::Ipv4Addr, time::Duration};
+
+    use super::*;
+    use crate::testing::{Fault, MockP0fServer};
+
+    fn server() -> MockP0fServer {
+        MockP0fServer::from_fn(|address| Some(Response::for_address(address))).unwrap()
+    }
+
+    #[tokio::test]
+    async fn cancelled_query() {
+        let server = server();
+        let mut p0f = AsyncP0f::new(server.path()).await.unwrap();
+        let address = Ipv4Addr::new(10, 0, 0, 2);
+
+        server.fail_next(Fault::Delay(Duration::from_millis(200)));
+        let cancelled = tokio::time::timeout(
+            Duration::from_millis(50),
+            p0f.query(Ipv4Addr::new(10, 0, 0, 1)),
+        )
+        .await;
+        assert!(cancelled.is_err());
+
+        let response = p0f.query(address).await.unwrap().unwrap();
+        assert_eq!(response.os_name, Some(address.to_string()));
+    }
+
+    #[tokio::test]
+    async fn cancelled_probe() {
+        let server = server();
+        let mut p0f = AsyncP0f::new(server.path())
+            .await
+            .unwrap()
+            .with_layout(ResponseLayout::Auto);
+        let address = Ipv4Addr::new(10, 0, 0, 2);
+
+        // Gives up on the query as soon as it has to wait, which is for the
+        // probe responses.
+        tokio::select! {
+            biased;
+            _ = p0f.query(Ipv4Addr::new(10, 0, 0, 1)) => {}
+            () = std::future::ready(()) => {}
+        }
+
+        let response = p0f.query(address).await.unwrap().unwrap();
+        assert_eq!(response.os_name, Some(address.to_string()));
+        assert_eq!(p0f.layout(), ResponseLayout::Stock);
+    }
+
+    #[tokio::test]
+    async fn failed_query() {
+        let server = server();
+        let mut p0f = AsyncP0f::new(server.path()).await.unwrap();
+        let address = Ipv4Addr::new(10, 0, 0, 2);
+
+        server.fail_next(Fault::DropMidResponse);
+        let err = p0f.query(Ipv4Addr::new(10, 0, 0, 1)).await.unwrap_err();
+        assert!(Fault::DropMidResponse.is_expected(&err));
+
+        let response = p0f.query(address).await.unwrap().unwrap();
+        assert_eq!(response.os_name, Some(address.to_string()));
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    os::{fd::OwnedFd, unix::net::UnixStream},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use socket2::{Domain, SockAddr, Socket, Type};
+
+use crate::{protocol::ResponseLayout, DecodeMode, Error, P0f, RetryPolicy};
+
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Timeouts {
+    pub(crate) connect: Option<Duration>,
+    pub(crate) read: Option<Duration>,
+    pub(crate) write: Option<Duration>,
+}
+
+#[derive(Clone, Debug)]
+pub struct P0fBuilder {
+    path: PathBuf,
+    timeouts: Timeouts,
+    retry: RetryPolicy,
+    decode_mode: DecodeMode,
+    layout: ResponseLayout,
+}
+
+impl P0fBuilder {
+    pub fn new<T: AsRef<Path>>(path: T) -> Self {
+        P0fBuilder {
+            path: path.as_ref().to_path_buf(),
+            timeouts: Timeouts::default(),
+            retry: RetryPolicy::default(),
+            decode_mode: DecodeMode::default(),
+            layout: ResponseLayout::default(),
+        }
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.connect = Some(timeout);
+        self
+    }
+
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.read = Some(timeout);
+        self
+    }
+
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.write = Some(timeout);
+        self
+    }
+
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn decode_mode(mut self, decode_mode: DecodeMode) -> Self {
+        self.decode_mode = decode_mode;
+        self
+    }
+
+    pub fn layout(mut self, layout: ResponseLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn connect(self) -> Result<P0f, Error> {
+        let stream = connect(&self.path, &self.timeouts, None)?;
+
+        let mut p0f = P0f {
+            path: self.path,
+            stream: Some(stream),
+            timeouts: self.timeouts,
+            retry: self.retry,
+            decode_mode: self.decode_mode,
+            layout: self.layout,
+            resolved_layout: None,
+        };
+        p0f.resolve_layout(None)?;
+
+        Ok(p0f)
+    }
+}
+
+pub(crate) fn connect(
+    path: &Path,
+    timeouts: &Timeouts,
+    deadline: Option<Instant>,
+) -> Result<UnixStream, Error> {
+    match remaining(timeouts.connect, deadline)? {
+        None => Ok(UnixStream::connect(path)?),
+        Some(timeout) => {
+            let socket = Socket::new(Domain::UNIX, Type::STREAM, None)?;
+            socket.connect_timeout(&SockAddr::unix(path)?, timeout)?;
+
+            Ok(OwnedFd::from(socket).into())
+        }
+    }
+}
+
+/// Picks the tighter of a configured timeout and whatever is left until the
+/// deadline, failing once the deadline has passed.
+pub(crate) fn remaining(
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+) -> Result<Option<Duration>, Error> {
+    let Some(deadline) = deadline else {
+        return Ok(timeout);
+    };
+
+    match deadline.checked_duration_since(Instant::now()) {
+        Some(left) if !left.is_zero() => Ok(Some(timeout.map_or(left, |t| t.min(left)))),
+        _ => Err(Error::Timeout),
+    }
+}
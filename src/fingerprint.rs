@@ -0,0 +1,573 @@
+//! Passive TCP and HTTP fingerprinting in-process, against a [`Database`] loaded from
+//! `p0f.fp`, without the p0f daemon.
+
+use crate::{
+    fpdb::{Database, Entry, Label, Section},
+    http::{HttpError, HttpMessage},
+    packet::{guess_distance, mtu, PacketError, TcpPacket},
+    signature::{
+        HttpSignature, HttpVersion, IpVersion, PayloadClass, Quirk, TcpOption, TcpSignature, Ttl,
+        WindowSize,
+    },
+    OsMatchQuality,
+};
+
+/// p0f's verdict on a single SYN or SYN+ACK, with the same fields as a
+/// [`Response`](crate::Response) has for them.
+#[derive(Clone, Debug)]
+pub struct Verdict {
+    pub os_name: Option<String>,
+    pub os_flavor: Option<String>,
+    pub os_match_q: OsMatchQuality,
+    pub distance: i16,
+    pub link_mtu: Option<u16>,
+    pub link_type: Option<String>,
+    /// The signature of the packet itself, as p0f prints it in its
+    /// `raw_sig`.
+    pub signature: TcpSignature,
+}
+
+/// p0f's verdict on a single HTTP request or response, with the same fields
+/// as a [`Response`](crate::Response) has for them.
+#[derive(Clone, Debug)]
+pub struct HttpVerdict {
+    pub http_name: Option<String>,
+    pub http_flavor: Option<String>,
+    /// Only ever set for requests.
+    pub language: Option<String>,
+    /// The `User-Agent` or `Server` doesn't contain what the matching
+    /// signature expects of it, p0f's `dishonest`.
+    pub dishonest: bool,
+    /// The signature of the message itself, as p0f prints it in its
+    /// `raw_sig`.
+    pub signature: HttpSignature,
+}
+
+pub struct Fingerprinter {
+    db: Database,
+}
+
+impl Fingerprinter {
+    pub fn new(db: Database) -> Self {
+        Fingerprinter { db }
+    }
+
+    pub fn database(&self) -> &Database {
+        &self.db
+    }
+
+    /// Fingerprints a packet that starts with its IP header. Packets other
+    /// than a SYN or SYN+ACK get `None`.
+    pub fn fingerprint(&self, packet: &[u8]) -> Result<Option<Verdict>, PacketError> {
+        Ok(self.fingerprint_packet(&TcpPacket::parse(packet)?))
+    }
+
+    pub fn fingerprint_packet(&self, packet: &TcpPacket) -> Option<Verdict> {
+        let section = if packet.is_syn() {
+            Section::TcpRequest
+        } else if packet.is_syn_ack() {
+            Section::TcpResponse
+        } else {
+            return None;
+        };
+
+        Some(self.verdict(section, &Observed::from_packet(packet), packet.signature()))
+    }
+
+    /// What p0f would say about a signature it printed as the `raw_sig` of
+    /// a SYN ([`Section::TcpRequest`]) or SYN+ACK ([`Section::TcpResponse`]),
+    /// such as one from its log. Signatures with wildcards, as only found in
+    /// `p0f.fp`, can't have come from a packet and are rejected.
+    pub fn fingerprint_signature(
+        &self,
+        signature: &TcpSignature,
+        section: Section,
+    ) -> Result<Verdict, &'static str> {
+        if !matches!(section, Section::TcpRequest | Section::TcpResponse) {
+            return Err("not a tcp section");
+        }
+
+        Ok(self.verdict(
+            section,
+            &Observed::from_signature(signature)?,
+            signature.clone(),
+        ))
+    }
+
+    fn verdict(&self, section: Section, observed: &Observed, signature: TcpSignature) -> Verdict {
+        let entries = match section {
+            Section::TcpResponse => &self.db.tcp_response,
+            _ => &self.db.tcp_request,
+        };
+
+        let found = find_match(entries, observed);
+        let distance = match &found {
+            Some(found) => match initial_ttl(found.sig.ittl).checked_sub(observed.ttl) {
+                Some(distance) if distance <= MAX_DIST => distance,
+                _ => guess_distance(observed.ttl),
+            },
+            None => guess_distance(observed.ttl),
+        };
+        let link_mtu = mtu(observed.version, observed.mss);
+
+        Verdict {
+            os_name: found.as_ref().map(|found| found.label.name.clone()),
+            os_flavor: found.as_ref().and_then(|found| found.label.flavor.clone()),
+            os_match_q: found
+                .as_ref()
+                .map_or(OsMatchQuality::Normal, Match::quality),
+            distance: distance.into(),
+            link_mtu,
+            link_type: link_mtu.and_then(|mtu| self.link_type(mtu).map(str::to_string)),
+            signature,
+        }
+    }
+
+    /// Fingerprints the raw bytes of an HTTP/1.x request or response, up to
+    /// at least the end of its headers.
+    pub fn fingerprint_http(&self, message: &[u8]) -> Result<HttpVerdict, HttpError> {
+        Ok(self.fingerprint_http_message(&HttpMessage::parse(message)?))
+    }
+
+    pub fn fingerprint_http_message(&self, message: &HttpMessage) -> HttpVerdict {
+        let entries = match message.is_request() {
+            true => &self.db.http_request,
+            false => &self.db.http_response,
+        };
+        let found = find_http_match(entries, message);
+
+        HttpVerdict {
+            http_name: found.map(|(label, _)| label.name.clone()),
+            http_flavor: found.and_then(|(label, _)| label.flavor.clone()),
+            language: message.language().map(str::to_string),
+            dishonest: found.is_some_and(|(_, sig)| {
+                message
+                    .software()
+                    .is_some_and(|software| !software.contains(&sig.expsw))
+            }),
+            signature: message.signature(),
+        }
+    }
+
+    /// The `[mtu]` label for a link MTU.
+    pub fn link_type(&self, mtu: u16) -> Option<&str> {
+        self.db
+            .mtu
+            .iter()
+            .find(|entry| entry.sigs.contains(&mtu))
+            .map(|entry| entry.label.as_str())
+    }
+}
+
+// p0f doesn't consider hosts further away than this.
+const MAX_DIST: u8 = 35;
+
+/// What matching looks at in a SYN or SYN+ACK, whether it comes from the
+/// packet itself or from its `raw_sig`.
+struct Observed<'a> {
+    version: IpVersion,
+    ttl: u8,
+    olen: u8,
+    /// 0 without an MSS option, like p0f has it.
+    mss: u16,
+    /// `None` if only known as a multiple of the MSS or MTU.
+    window: Option<u16>,
+    /// The window as p0f prints it, the multiple if it found one.
+    wsize: WindowSize,
+    wscale: u8,
+    olayout: &'a [TcpOption],
+    quirks: &'a [Quirk],
+    payload: bool,
+}
+
+impl<'a> Observed<'a> {
+    fn from_packet(packet: &'a TcpPacket) -> Self {
+        Observed {
+            version: packet.version(),
+            ttl: packet.ttl,
+            olen: packet.ip_olen,
+            mss: packet.mss.unwrap_or(0),
+            window: Some(packet.window),
+            wsize: packet.window_size(),
+            wscale: packet.wscale.unwrap_or(0),
+            olayout: &packet.olayout,
+            quirks: &packet.quirks,
+            payload: packet.payload_len != 0,
+        }
+    }
+
+    fn from_signature(sig: &'a TcpSignature) -> Result<Self, &'static str> {
+        if sig.version == IpVersion::Any {
+            return Err("ip version must not be a wildcard");
+        }
+        let mss = sig.mss.ok_or("mss must not be a wildcard")?;
+        // p0f only prints the window as is when it isn't a multiple, and a
+        // multiple doesn't tell which of the sizes it tries it was of.
+        let window = match sig.wsize {
+            WindowSize::Value(window) => Some(window),
+            WindowSize::Mss(_) | WindowSize::Mtu(_) => None,
+            WindowSize::Any | WindowSize::Mod(_) => {
+                return Err("window size must not be a wildcard")
+            }
+        };
+
+        Ok(Observed {
+            version: sig.version,
+            ttl: match sig.ittl {
+                Ttl::Exact(ttl) | Ttl::Distance(ttl, _) | Ttl::Guessed(ttl) | Ttl::Bad(ttl) => ttl,
+            },
+            olen: sig.olen,
+            mss,
+            window,
+            wsize: sig.wsize,
+            wscale: sig.wscale.ok_or("window scale must not be a wildcard")?,
+            olayout: &sig.olayout,
+            quirks: &sig.quirks,
+            payload: match sig.pclass {
+                PayloadClass::Zero => false,
+                PayloadClass::NonZero => true,
+                PayloadClass::Any => return Err("payload class must not be a wildcard"),
+            },
+        })
+    }
+}
+
+struct Match<'a> {
+    label: &'a Label,
+    sig: &'a TcpSignature,
+    fuzzy: bool,
+}
+
+impl Match<'_> {
+    fn quality(&self) -> OsMatchQuality {
+        match (self.fuzzy, self.label.is_generic()) {
+            (false, false) => OsMatchQuality::Normal,
+            (false, true) => OsMatchQuality::Generic,
+            (true, false) => OsMatchQuality::Fuzzy,
+            (true, true) => OsMatchQuality::FuzzyGeneric,
+        }
+    }
+}
+
+/// Picks a signature the way p0f does: the first specific one that matches
+/// exactly, or failing that the first generic one that does, or failing that
+/// the first one that matches fuzzily.
+fn find_match<'a>(entries: &'a [Entry<TcpSignature>], observed: &Observed) -> Option<Match<'a>> {
+    let mut generic = None;
+    let mut fuzzy = None::<Match>;
+
+    for entry in entries {
+        for sig in &entry.sigs {
+            let Some(is_fuzzy) = compare(sig, observed, fuzzy.is_none()) else {
+                continue;
+            };
+
+            let found = Match {
+                label: &entry.label,
+                sig,
+                fuzzy: is_fuzzy,
+            };
+            match (is_fuzzy, entry.label.is_generic()) {
+                (false, false) => return Some(found),
+                (false, true) => {
+                    generic.get_or_insert(found);
+                }
+                (true, _) => {
+                    fuzzy.get_or_insert(found);
+                }
+            }
+        }
+    }
+
+    // p0f never settles for a fuzzy match on a userland tool.
+    generic.or(fuzzy.filter(|found| found.label.class.is_some()))
+}
+
+/// Whether `sig` matches at all, and if so, whether only fuzzily. Quirks are
+/// only allowed to differ while `fuzzy_quirks` holds.
+fn compare(sig: &TcpSignature, observed: &Observed, fuzzy_quirks: bool) -> Option<bool> {
+    let mut fuzzy = false;
+
+    if sig.version != IpVersion::Any && sig.version != observed.version {
+        return None;
+    }
+    if sig.olayout != observed.olayout {
+        return None;
+    }
+
+    // Signatures for both IP versions can't require quirks that only one of
+    // them has.
+    let applies = |quirk: &&Quirk| match (sig.version, observed.version) {
+        (IpVersion::Any, IpVersion::V4) => **quirk != Quirk::FlowId,
+        (IpVersion::Any, _) => !matches!(quirk, Quirk::Df | Quirk::NonZeroId | Quirk::ZeroId),
+        _ => true,
+    };
+    let mut deleted = sig
+        .quirks
+        .iter()
+        .filter(applies)
+        .filter(|quirk| !observed.quirks.contains(quirk));
+    let mut added = observed
+        .quirks
+        .iter()
+        .filter(|quirk| !sig.quirks.iter().filter(applies).any(|sig| sig == *quirk));
+    if deleted.clone().next().is_some() || added.clone().next().is_some() {
+        // Losing df or id+, or gaining id- or ecn, only makes for a fuzzy
+        // match.
+        if !fuzzy_quirks
+            || deleted.any(|quirk| !matches!(quirk, Quirk::Df | Quirk::NonZeroId))
+            || added.any(|quirk| !matches!(quirk, Quirk::ZeroId | Quirk::Ecn))
+        {
+            return None;
+        }
+        fuzzy = true;
+    }
+
+    if sig.olen != observed.olen
+        || sig.mss.is_some_and(|mss| mss != observed.mss)
+        || sig.wscale.is_some_and(|wscale| wscale != observed.wscale)
+    {
+        return None;
+    }
+    match sig.pclass {
+        PayloadClass::Any => {}
+        PayloadClass::Zero if !observed.payload => {}
+        PayloadClass::NonZero if observed.payload => {}
+        _ => return None,
+    }
+
+    // A TTL that is too high or too far off is tolerated too, except for
+    // signatures of systems known to pick odd ones.
+    let ittl = initial_ttl(sig.ittl);
+    match sig.ittl {
+        Ttl::Bad(_) if ittl < observed.ttl => return None,
+        Ttl::Bad(_) => {}
+        _ if ittl < observed.ttl || ittl - observed.ttl > MAX_DIST => fuzzy = true,
+        _ => {}
+    }
+
+    // A multiple only matches one of the same kind, so a window p0f takes
+    // for a multiple of the MTU never matches an `mss*` signature.
+    let window_matches = match sig.wsize {
+        WindowSize::Any => true,
+        WindowSize::Value(value) => observed.window == Some(value),
+        WindowSize::Mss(_) | WindowSize::Mtu(_) => observed.wsize == sig.wsize,
+        WindowSize::Mod(modulo) => {
+            modulo != 0
+                && observed
+                    .window
+                    .is_some_and(|window| window.is_multiple_of(modulo))
+        }
+    };
+
+    window_matches.then_some(fuzzy)
+}
+
+/// The first specific label with a signature matching `message`, or failing
+/// that the first generic one. Unlike for TCP, there are no fuzzy matches.
+fn find_http_match<'a>(
+    entries: &'a [Entry<HttpSignature>],
+    message: &HttpMessage,
+) -> Option<(&'a Label, &'a HttpSignature)> {
+    let mut generic = None;
+
+    for entry in entries {
+        for sig in &entry.sigs {
+            if !http_matches(sig, message) {
+                continue;
+            }
+
+            if !entry.label.is_generic() {
+                return Some((&entry.label, sig));
+            }
+            generic.get_or_insert((&entry.label, sig));
+        }
+    }
+
+    generic
+}
+
+fn http_matches(sig: &HttpSignature, message: &HttpMessage) -> bool {
+    if sig.version != HttpVersion::Any && sig.version != message.version {
+        return false;
+    }
+
+    // The headers of the signature have to appear in the same order, with
+    // the expected values, save for optional ones that don't appear at all.
+    // An optional header that does appear still has to be in its place.
+    let position = |name: &str, from: usize| {
+        message.headers[from..]
+            .iter()
+            .position(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|i| from + i)
+    };
+    let mut next = 0;
+    for header in &sig.horder {
+        let Some(i) = position(&header.name, next) else {
+            if header.optional && message.header(&header.name).is_none() {
+                continue;
+            }
+            return false;
+        };
+        if header
+            .value
+            .as_ref()
+            .is_some_and(|value| !message.headers[i].1.contains(value.as_str()))
+        {
+            return false;
+        }
+        next = i + 1;
+    }
+
+    if sig
+        .habsent
+        .iter()
+        .any(|name| message.header(name).is_some())
+    {
+        return false;
+    }
+
+    // Nor may the message have headers the signature doesn't know about,
+    // unless they are optional anyway.
+    message.headers.iter().all(|(name, _)| {
+        message.is_optional(name)
+            || sig
+                .horder
+                .iter()
+                .any(|header| header.name.eq_ignore_ascii_case(name))
+    })
+}
+
+fn initial_ttl(ttl: Ttl) -> u8 {
+    match ttl {
+        Ttl::Exact(ttl) | Ttl::Guessed(ttl) | Ttl::Bad(ttl) => ttl,
+        Ttl::Distance(ttl, distance) => ttl.saturating_add(distance),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STOCK: &str = include_str!("../tests/data/p0f.fp");
+
+    // The options of a Linux SYN: mss, sok, ts, nop and ws.
+    fn linux_options(mss: u16, wscale: u8) -> Vec<u8> {
+        let [hi, lo] = mss.to_be_bytes();
+        vec![
+            2, 4, hi, lo, 4, 2, 8, 10, 0, 0, 0, 1, 0, 0, 0, 0, 1, 3, 3, wscale,
+        ]
+    }
+
+    /// An IPv4 SYN with a nonzero IP ID, and the DF bit if `df` holds.
+    fn syn(ttl: u8, df: bool, window: u16, options: &[u8]) -> Vec<u8> {
+        let total = (20 + 20 + options.len()) as u16;
+        let mut packet = vec![0x45, 0];
+        packet.extend_from_slice(&total.to_be_bytes());
+        packet.extend_from_slice(&[0x12, 0x34, if df { 0x40 } else { 0 }, 0, ttl, 6, 0, 0]);
+        packet.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+
+        packet.extend_from_slice(&[0xc0, 0x01, 0, 80, 0, 0, 0, 1, 0, 0, 0, 0]);
+        packet.extend_from_slice(&[(((20 + options.len()) / 4) << 4) as u8, 0x02]);
+        packet.extend_from_slice(&window.to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 0]);
+        packet.extend_from_slice(options);
+        packet
+    }
+
+    fn stock() -> Fingerprinter {
+        Fingerprinter::new(Database::parse(STOCK).unwrap())
+    }
+
+    fn fingerprint(fp: &Fingerprinter, packet: &[u8]) -> Verdict {
+        fp.fingerprint(packet).unwrap().unwrap()
+    }
+
+    #[test]
+    fn window_multiples() {
+        let fp = stock();
+
+        // A multiple of 1460 rather than of the MSS.
+        let verdict = fingerprint(&fp, &syn(64, true, 14600, &linux_options(1400, 4)));
+        assert_eq!(verdict.os_name.as_deref(), Some("Linux"));
+        assert_eq!(verdict.os_flavor.as_deref(), Some("3.1-3.10"));
+        assert!(matches!(verdict.os_match_q, OsMatchQuality::Normal));
+        assert_eq!(verdict.distance, 0);
+        assert_eq!(verdict.signature.wsize, WindowSize::Mss(10));
+
+        // A multiple of the MSS less the timestamps.
+        let verdict = fingerprint(&fp, &syn(60, true, 14480, &linux_options(1460, 4)));
+        assert_eq!(verdict.os_flavor.as_deref(), Some("3.1-3.10"));
+        assert!(matches!(verdict.os_match_q, OsMatchQuality::Normal));
+        assert_eq!(verdict.distance, 4);
+        assert_eq!(verdict.signature.wsize, WindowSize::Mss(10));
+
+        let verdict = fingerprint(&fp, &syn(128, false, 3000, &[2, 4, 0x05, 0xb4, 1, 3, 3, 0]));
+        assert_eq!(verdict.os_name.as_deref(), Some("OpenVMS"));
+        assert!(matches!(verdict.os_match_q, OsMatchQuality::Normal));
+        assert_eq!(verdict.signature.wsize, WindowSize::Mtu(2));
+        assert_eq!(
+            verdict.signature.to_string(),
+            "4:128+0:0:1460:mtu*2,0:mss,nop,ws::0"
+        );
+
+        let verdict = fp
+            .fingerprint_signature(&verdict.signature, Section::TcpRequest)
+            .unwrap();
+        assert_eq!(verdict.os_name.as_deref(), Some("OpenVMS"));
+    }
+
+    #[test]
+    fn window_multiple_kinds() {
+        let db = "classes = unix\n\
+                  [tcp:request]\n\
+                  label = s:unix:Mss:1\n\
+                  sig = 4:128:0:1460:mss*2,0:mss,nop,ws::0\n\
+                  label = s:unix:Mtu:1\n\
+                  sig = 4:128:0:1460:mtu*2,0:mss,nop,ws::0\n";
+        let fp = Fingerprinter::new(Database::parse(db).unwrap());
+        let options = [2, 4, 0x05, 0xb4, 1, 3, 3, 0];
+
+        let verdict = fingerprint(&fp, &syn(128, false, 2920, &options));
+        assert_eq!(verdict.os_name.as_deref(), Some("Mss"));
+        let verdict = fingerprint(&fp, &syn(128, false, 3000, &options));
+        assert_eq!(verdict.os_name.as_deref(), Some("Mtu"));
+        let verdict = fingerprint(&fp, &syn(128, false, 3001, &options));
+        assert_eq!(verdict.os_name, None);
+        assert_eq!(verdict.signature.wsize, WindowSize::Value(3001));
+    }
+
+    #[test]
+    fn optional_header() {
+        let db = "[http:request]\n\
+                  label = s:!:Test:1.x\n\
+                  sig = *:Host,User-Agent,?Referer,Accept::Test\n";
+        let fp = Fingerprinter::new(Database::parse(db).unwrap());
+        let request = |headers: &str| {
+            let message = format!("GET / HTTP/1.1\r\n{headers}\r\n");
+            fp.fingerprint_http(message.as_bytes()).unwrap().http_name
+        };
+
+        let expected = Some("Test".to_string());
+        assert_eq!(
+            request("Host: a\r\nUser-Agent: Test\r\nAccept: */*\r\n"),
+            expected
+        );
+        assert_eq!(
+            request("Host: a\r\nUser-Agent: Test\r\nReferer: b\r\nAccept: */*\r\n"),
+            expected
+        );
+        assert_eq!(
+            request("Referer: b\r\nHost: a\r\nUser-Agent: Test\r\nAccept: */*\r\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn fuzzy_distance() {
+        let verdict = fingerprint(&stock(), &syn(20, true, 14600, &linux_options(1460, 4)));
+        assert_eq!(verdict.os_flavor.as_deref(), Some("3.1-3.10"));
+        assert!(matches!(verdict.os_match_q, OsMatchQuality::Fuzzy));
+        assert_eq!(verdict.distance, 12);
+    }
+}
@@ -0,0 +1,513 @@
+//! Parser for `p0f.fp`, the fingerprint database p0f loads on startup.
+//!
+//! The database is split into sections, `[mtu]`, `[tcp:request]`,
+//! `[tcp:response]`, `[http:request]` and `[http:response]`. Within a section,
+//! every `label =` line starts a new entry, optionally followed by a `sys =`
+//! line, and the `sig =` lines after it belong to that entry. The label of the
+//! entry that matched is what p0f reports as `os_name`/`os_flavor` (TCP),
+//! `http_name`/`http_flavor` (HTTP) or `link_type` (MTU).
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read as _},
+    path::Path,
+    str::FromStr,
+};
+
+use crate::signature::{HttpSignature, TcpSignature};
+
+#[derive(Debug, thiserror::Error)]
+pub enum FpdbError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("line {line}: {reason}")]
+    Malformed { line: usize, reason: &'static str },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Section {
+    Mtu,
+    TcpRequest,
+    TcpResponse,
+    HttpRequest,
+    HttpResponse,
+}
+
+impl Section {
+    fn from_header(header: &str) -> Option<Section> {
+        match header {
+            "mtu" => Some(Section::Mtu),
+            "tcp:request" => Some(Section::TcpRequest),
+            "tcp:response" => Some(Section::TcpResponse),
+            "http:request" => Some(Section::HttpRequest),
+            "http:response" => Some(Section::HttpResponse),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Database {
+    /// The classes `label` and `sys` lines may refer to, e.g. `win` and `unix`.
+    pub classes: Vec<String>,
+    /// Operating systems p0f recognizes in a `User-Agent`, see [`UaOs`].
+    pub ua_os: Vec<UaOs>,
+    pub mtu: Vec<MtuEntry>,
+    pub tcp_request: Vec<Entry<TcpSignature>>,
+    pub tcp_response: Vec<Entry<TcpSignature>>,
+    pub http_request: Vec<Entry<HttpSignature>>,
+    pub http_response: Vec<Entry<HttpSignature>>,
+}
+
+/// An entry of the `ua_os` line. `iOS=[iPad]` reports `iOS` for any
+/// `User-Agent` containing `iPad`, a plain `Linux` looks for `Linux` itself.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UaOs {
+    pub name: String,
+    pub ua: Option<String>,
+}
+
+impl UaOs {
+    /// The string to look for in the `User-Agent`.
+    pub fn ua(&self) -> &str {
+        self.ua.as_deref().unwrap_or(&self.name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MtuEntry {
+    pub label: String,
+    pub sigs: Vec<u16>,
+    /// Line of the `label` in the database.
+    pub line: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry<S> {
+    pub label: Label,
+    pub sys: Vec<Sys>,
+    pub sigs: Vec<S>,
+    /// Line of the `label` in the database.
+    pub line: usize,
+}
+
+/// A `type:class:name:flavor` label, such as `s:unix:Linux:3.11 and newer`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Label {
+    pub kind: LabelKind,
+    /// `None` for `!`, which marks userland tools and applications rather
+    /// than operating systems.
+    pub class: Option<String>,
+    pub name: String,
+    pub flavor: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LabelKind {
+    /// `s`, a signature for one particular system.
+    Specific,
+    /// `g`, a catch-all signature for a whole family of systems.
+    Generic,
+}
+
+impl Label {
+    pub fn is_generic(&self) -> bool {
+        self.kind == LabelKind::Generic
+    }
+}
+
+impl FromStr for Label {
+    type Err = &'static str;
+
+    fn from_str(label: &str) -> Result<Self, Self::Err> {
+        let mut fields = label.splitn(4, ':');
+        let (Some(kind), Some(class), Some(name), Some(flavor)) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return Err("label must be type:class:name:flavor");
+        };
+
+        let kind = match kind {
+            "s" => LabelKind::Specific,
+            "g" => LabelKind::Generic,
+            _ => return Err("label type must be s or g"),
+        };
+        let class = match class {
+            "" => return Err("empty label class"),
+            "!" => None,
+            class => Some(class.to_string()),
+        };
+        if name.is_empty() {
+            return Err("empty label name");
+        }
+
+        Ok(Label {
+            kind,
+            class,
+            name: name.to_string(),
+            flavor: (!flavor.is_empty()).then(|| flavor.to_string()),
+        })
+    }
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            LabelKind::Specific => 's',
+            LabelKind::Generic => 'g',
+        };
+
+        write!(
+            f,
+            "{kind}:{}:{}:{}",
+            self.class.as_deref().unwrap_or("!"),
+            self.name,
+            self.flavor.as_deref().unwrap_or_default()
+        )
+    }
+}
+
+/// An entry of a `sys` line, the systems an application label is expected
+/// to run on.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Sys {
+    /// `@unix`, any system of a class.
+    Class(String),
+    /// `Linux`, a system by its label name.
+    Name(String),
+}
+
+impl fmt::Display for Sys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Sys::Class(class) => write!(f, "@{class}"),
+            Sys::Name(name) => f.write_str(name),
+        }
+    }
+}
+
+impl Database {
+    pub fn load<T: AsRef<Path>>(path: T) -> Result<Self, FpdbError> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, FpdbError> {
+        let mut parser = Parser::default();
+        for (i, line) in text.lines().enumerate() {
+            parser
+                .line(i + 1, line)
+                .map_err(|reason| FpdbError::Malformed {
+                    line: i + 1,
+                    reason,
+                })?;
+        }
+
+        Ok(parser.db)
+    }
+
+    /// Every TCP and HTTP label, in the order they appear in the database.
+    pub fn labels(&self) -> impl Iterator<Item = &Label> {
+        let tcp = self.tcp_request.iter().chain(&self.tcp_response);
+        let http = self.http_request.iter().chain(&self.http_response);
+
+        tcp.map(|entry| &entry.label)
+            .chain(http.map(|entry| &entry.label))
+    }
+}
+
+impl FromStr for Database {
+    type Err = FpdbError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Self::parse(text)
+    }
+}
+
+#[derive(Default)]
+struct Parser {
+    db: Database,
+    section: Option<Section>,
+    // Whether the current entry already got a `sig`, after which `sys` is no
+    // longer allowed.
+    has_sigs: bool,
+}
+
+impl Parser {
+    fn line(&mut self, number: usize, line: &str) -> Result<(), &'static str> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') {
+            return Ok(());
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let header = header.strip_suffix(']').ok_or("unterminated section")?;
+            self.section = Some(Section::from_header(header).ok_or("unknown section")?);
+            self.has_sigs = false;
+            return Ok(());
+        }
+
+        let (key, value) = line.split_once('=').ok_or("expected key = value")?;
+        let value = value.trim();
+        match key.trim() {
+            "classes" => {
+                if !self.db.classes.is_empty() {
+                    return Err("classes already defined");
+                }
+                self.db.classes = list(value).map(str::to_string).collect();
+            }
+            "ua_os" => {
+                if !self.db.ua_os.is_empty() {
+                    return Err("ua_os already defined");
+                }
+                self.db.ua_os = ua_os(value)?;
+            }
+            "label" => self.label(number, value)?,
+            "sys" => self.sys(value)?,
+            "sig" => self.sig(value)?,
+            _ => return Err("unknown directive"),
+        }
+
+        Ok(())
+    }
+
+    fn label(&mut self, line: usize, value: &str) -> Result<(), &'static str> {
+        let section = self.section.ok_or("label outside of a section")?;
+        self.has_sigs = false;
+
+        if section == Section::Mtu {
+            self.db.mtu.push(MtuEntry {
+                label: value.to_string(),
+                sigs: Vec::new(),
+                line,
+            });
+            return Ok(());
+        }
+
+        let label = value.parse::<Label>()?;
+        if let Some(class) = &label.class {
+            if !self.db.classes.contains(class) {
+                return Err("unknown label class");
+            }
+        }
+
+        match section {
+            Section::TcpRequest => self.db.tcp_request.push(entry(label, line)),
+            Section::TcpResponse => self.db.tcp_response.push(entry(label, line)),
+            Section::HttpRequest => self.db.http_request.push(entry(label, line)),
+            Section::HttpResponse => self.db.http_response.push(entry(label, line)),
+            Section::Mtu => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    fn sys(&mut self, value: &str) -> Result<(), &'static str> {
+        if self.has_sigs {
+            return Err("sys after sig");
+        }
+
+        let sys = list(value)
+            .map(|sys| match sys.strip_prefix('@') {
+                Some(class) if self.db.classes.iter().any(|known| known == class) => {
+                    Ok(Sys::Class(class.to_string()))
+                }
+                Some(_) => Err("unknown sys class"),
+                None => Ok(Sys::Name(sys.to_string())),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let entry_sys = match self.section.ok_or("sys outside of a section")? {
+            Section::Mtu => return Err("sys in the mtu section"),
+            Section::TcpRequest => self.db.tcp_request.last_mut().map(|entry| &mut entry.sys),
+            Section::TcpResponse => self.db.tcp_response.last_mut().map(|entry| &mut entry.sys),
+            Section::HttpRequest => self.db.http_request.last_mut().map(|entry| &mut entry.sys),
+            Section::HttpResponse => self.db.http_response.last_mut().map(|entry| &mut entry.sys),
+        };
+        let entry_sys = entry_sys.ok_or("sys before label")?;
+        if !entry_sys.is_empty() {
+            return Err("sys already defined for this label");
+        }
+        *entry_sys = sys;
+
+        Ok(())
+    }
+
+    fn sig(&mut self, value: &str) -> Result<(), &'static str> {
+        match self.section.ok_or("sig outside of a section")? {
+            Section::Mtu => {
+                let mtu = value.parse().map_err(|_| "invalid mtu")?;
+                last(&mut self.db.mtu)?.sigs.push(mtu);
+            }
+            Section::TcpRequest => last(&mut self.db.tcp_request)?.sigs.push(value.parse()?),
+            Section::TcpResponse => last(&mut self.db.tcp_response)?.sigs.push(value.parse()?),
+            Section::HttpRequest => last(&mut self.db.http_request)?.sigs.push(value.parse()?),
+            Section::HttpResponse => last(&mut self.db.http_response)?.sigs.push(value.parse()?),
+        }
+        self.has_sigs = true;
+
+        Ok(())
+    }
+}
+
+fn entry<S>(label: Label, line: usize) -> Entry<S> {
+    Entry {
+        label,
+        sys: Vec::new(),
+        sigs: Vec::new(),
+        line,
+    }
+}
+
+fn last<T>(entries: &mut [T]) -> Result<&mut T, &'static str> {
+    entries.last_mut().ok_or("sig before label")
+}
+
+fn list(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+fn ua_os(value: &str) -> Result<Vec<UaOs>, &'static str> {
+    list(value)
+        .map(|item| match item.split_once('=') {
+            Some((name, ua)) => {
+                let ua = ua
+                    .strip_prefix('[')
+                    .and_then(|ua| ua.strip_suffix(']'))
+                    .ok_or("ua_os value must be in brackets")?;
+
+                Ok(UaOs {
+                    name: name.to_string(),
+                    ua: Some(ua.to_string()),
+                })
+            }
+            None => Ok(UaOs {
+                name: item.to_string(),
+                ua: None,
+            }),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STOCK: &str = include_str!("../tests/data/p0f.fp");
+
+    #[test]
+    fn stock() {
+        let db = Database::parse(STOCK).unwrap();
+
+        assert_eq!(db.classes, ["win", "unix", "other"]);
+        assert_eq!(db.ua_os.len(), 9);
+        assert_eq!(db.ua_os[2].name, "iOS");
+        assert_eq!(db.ua_os[2].ua(), "iPad");
+        assert_eq!(db.ua_os[0].ua(), "Linux");
+
+        assert_eq!(db.mtu.len(), 14);
+        assert_eq!(db.mtu[0].label, "Ethernet or modem");
+        assert_eq!(db.mtu[0].sigs, [576, 1500]);
+        assert_eq!(db.tcp_request.len(), 46);
+        assert_eq!(db.tcp_response.len(), 17);
+        assert_eq!(db.http_request.len(), 53);
+        assert_eq!(db.http_response.len(), 8);
+
+        let linux = &db.tcp_request[0];
+        assert_eq!(linux.label.to_string(), "s:unix:Linux:3.11 and newer");
+        assert_eq!(
+            STOCK.lines().nth(linux.line - 1).unwrap().trim(),
+            "label = s:unix:Linux:3.11 and newer"
+        );
+        assert!(db.http_request.iter().any(|entry| !entry.sys.is_empty()));
+        assert!(db.labels().any(|label| label.class.is_none()));
+    }
+
+    #[test]
+    fn stock_round_trip() {
+        let db = Database::parse(STOCK).unwrap();
+        let tcp = db.tcp_request.iter().chain(&db.tcp_response);
+        let http = db.http_request.iter().chain(&db.http_response);
+        let mut sigs = tcp
+            .flat_map(|entry| entry.sigs.iter().map(ToString::to_string))
+            .chain(http.flat_map(|entry| entry.sigs.iter().map(ToString::to_string)));
+        let mut labels = db.labels();
+
+        let mut section = None;
+        for line in STOCK.lines() {
+            if let Some(header) = line.strip_prefix('[') {
+                section = Section::from_header(header.trim_end_matches(']'));
+            }
+            if section == Some(Section::Mtu) {
+                continue;
+            }
+            match line
+                .split_once('=')
+                .map(|(key, value)| (key.trim(), value.trim()))
+            {
+                Some(("sig", sig)) => assert_eq!(sigs.next().unwrap(), sig),
+                Some(("label", label)) => assert_eq!(labels.next().unwrap().to_string(), label),
+                _ => {}
+            }
+        }
+        assert!(sigs.next().is_none());
+        assert!(labels.next().is_none());
+    }
+
+    #[test]
+    fn malformed() {
+        for (text, line, reason) in [
+            ("[tcp]", 1, "unknown section"),
+            ("[mtu", 1, "unterminated section"),
+            ("label = s:unix:Linux:", 1, "label outside of a section"),
+            ("[mtu]\nsig = 1500", 2, "sig before label"),
+            ("[mtu]\nlabel = DSL\nsig = x", 3, "invalid mtu"),
+            (
+                "classes = win\n[tcp:request]\nlabel = s:unix:Linux:",
+                3,
+                "unknown label class",
+            ),
+            (
+                "classes = unix\n[tcp:request]\nlabel = x:unix:Linux:",
+                3,
+                "label type must be s or g",
+            ),
+            (
+                "classes = unix\n[tcp:request]\nlabel = s:unix:Linux:\nsig = 4:64:0",
+                4,
+                "tcp signature must have 8 fields",
+            ),
+            (
+                "[http:request]\nlabel = s:!:curl:\nsig = *:::\nsys = Linux",
+                4,
+                "sys after sig",
+            ),
+            (
+                "[http:request]\nlabel = s:!:curl:\nsys = @unix",
+                3,
+                "unknown sys class",
+            ),
+            (
+                "classes = win\nclasses = unix",
+                2,
+                "classes already defined",
+            ),
+            ("ua_os = iOS=iPad", 1, "ua_os value must be in brackets"),
+            ("[mtu]\nfoo = bar", 2, "unknown directive"),
+        ] {
+            match Database::parse(text) {
+                Err(FpdbError::Malformed {
+                    line: got_line,
+                    reason: got_reason,
+                }) => assert_eq!((got_line, got_reason), (line, reason), "{text}"),
+                result => panic!("{text}: {result:?}"),
+            }
+        }
+    }
+}
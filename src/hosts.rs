@@ -0,0 +1,134 @@
+//! Per-host state built up from individual packets, the way the p0f daemon
+//! keeps it for its API.
+
+use std::{collections::HashMap, net::IpAddr, time::Duration};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    fingerprint::{Fingerprinter, Verdict},
+    packet::TcpPacket,
+    pcap::{InterfaceId, LinkType},
+    OsMatchQuality, Response,
+};
+
+#[derive(Clone, Debug)]
+pub struct HostRecord {
+    pub address: IpAddr,
+    /// What the API would answer for `address`.
+    pub response: Response,
+    /// Link-layer type of the capture the host was last seen in. Not to be
+    /// confused with `response.link_type`, p0f's guess from the MTU.
+    pub link_type: Option<LinkType>,
+    /// Capture interface the host was last seen on.
+    pub interface: Option<InterfaceId>,
+}
+
+pub struct HostTracker<'a> {
+    fingerprinter: &'a Fingerprinter,
+    records: Vec<HostRecord>,
+    index: HashMap<IpAddr, usize>,
+}
+
+impl<'a> HostTracker<'a> {
+    pub fn new(fingerprinter: &'a Fingerprinter) -> Self {
+        HostTracker {
+            fingerprinter,
+            records: Vec::new(),
+            index: HashMap::new(),
+        }
+    }
+
+    /// Fingerprints a packet seen at `time` and updates the record of the
+    /// host that sent it. Only SYN and SYN+ACK packets count, each as one
+    /// connection of their sender; anything else is ignored.
+    pub fn observe(&mut self, time: DateTime<Utc>, packet: &TcpPacket) -> Option<&mut HostRecord> {
+        let verdict = self.fingerprinter.fingerprint_packet(packet)?;
+        let address = packet.source.ip();
+
+        let index = *self.index.entry(address).or_insert_with(|| {
+            self.records.push(HostRecord {
+                address,
+                response: new_response(time),
+                link_type: None,
+                interface: None,
+            });
+            self.records.len() - 1
+        });
+        let record = &mut self.records[index];
+        update(&mut record.response, time, verdict);
+
+        Some(record)
+    }
+
+    pub fn get(&self, address: IpAddr) -> Option<&HostRecord> {
+        self.index.get(&address).map(|&index| &self.records[index])
+    }
+
+    /// Every host seen so far, in the order they were first seen.
+    pub fn records(&self) -> &[HostRecord] {
+        &self.records
+    }
+
+    pub fn into_records(self) -> Vec<HostRecord> {
+        self.records
+    }
+}
+
+fn new_response(time: DateTime<Utc>) -> Response {
+    Response {
+        first_seen: time,
+        last_seen: time,
+        total_conn: 0,
+        uptime_min: None,
+        up_mod_days: Duration::ZERO,
+        last_nat: None,
+        last_chg: None,
+        distance: None,
+        bad_sw: None,
+        os_match_q: OsMatchQuality::Normal,
+        os_name: None,
+        os_flavor: None,
+        http_name: None,
+        http_flavor: None,
+        link_mtu: None,
+        link_type: None,
+        language: None,
+    }
+}
+
+fn update(response: &mut Response, time: DateTime<Utc>, verdict: Verdict) {
+    response.first_seen = response.first_seen.min(time);
+    response.last_seen = response.last_seen.max(time);
+    response.total_conn = response.total_conn.saturating_add(1);
+    response.distance = Some(verdict.distance);
+    if verdict.link_mtu.is_some() {
+        response.link_mtu = verdict.link_mtu;
+        response.link_type = verdict.link_type;
+    }
+
+    // Like p0f, don't let a vaguer match replace a better one.
+    if verdict.os_name.is_none()
+        || response.os_name.is_some() && rank(&verdict.os_match_q) > rank(&response.os_match_q)
+    {
+        return;
+    }
+    if response.os_name.is_some()
+        && (response.os_name != verdict.os_name || response.os_flavor != verdict.os_flavor)
+    {
+        response.last_chg = Some(time);
+    }
+    response.os_name = verdict.os_name;
+    response.os_flavor = verdict.os_flavor;
+    response.os_match_q = verdict.os_match_q;
+}
+
+fn rank(quality: &OsMatchQuality) -> u8 {
+    match quality {
+        OsMatchQuality::Normal => 0,
+        OsMatchQuality::Fuzzy => 1,
+        OsMatchQuality::Generic => 2,
+        OsMatchQuality::FuzzyGeneric => 3,
+        OsMatchQuality::Unknown(_) => 4,
+    }
+}
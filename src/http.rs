@@ -0,0 +1,240 @@
+//! HTTP/1.x messages as p0f looks at them, taken from their raw bytes, e.g.
+//! by a reverse proxy, rather than from sniffed traffic. See
+//! [`Fingerprinter::fingerprint_http`](crate::fingerprint::Fingerprinter::fingerprint_http)
+//! for matching them against `p0f.fp`.
+
+use crate::{
+    fpdb::Section,
+    signature::{Header, HttpSignature, HttpVersion},
+};
+
+mod language;
+
+// Headers that come and go between requests from the same software, and
+// are never required to be there.
+const REQUEST_OPTIONAL: [&str; 11] = [
+    "Cookie",
+    "Referer",
+    "Origin",
+    "Range",
+    "If-Modified-Since",
+    "If-None-Match",
+    "Via",
+    "X-Forwarded-For",
+    "Authorization",
+    "Proxy-Authorization",
+    "Cache-Control",
+];
+const RESPONSE_OPTIONAL: [&str; 12] = [
+    "Set-Cookie",
+    "Last-Modified",
+    "ETag",
+    "Content-Length",
+    "Content-Disposition",
+    "Cache-Control",
+    "Expires",
+    "Pragma",
+    "Location",
+    "Refresh",
+    "Content-Range",
+    "Vary",
+];
+
+// Headers whose values are left out of a signature, as they say nothing
+// about the software.
+const REQUEST_SKIP_VALUE: [&str; 2] = ["Host", "User-Agent"];
+const RESPONSE_SKIP_VALUE: [&str; 3] = ["Date", "Content-Type", "Server"];
+
+// Headers whose absence is worth noting in a signature.
+const REQUEST_COMMON: [&str; 6] = [
+    "Accept",
+    "Accept-Encoding",
+    "Accept-Language",
+    "Accept-Charset",
+    "Connection",
+    "Keep-Alive",
+];
+const RESPONSE_COMMON: [&str; 5] = [
+    "Content-Type",
+    "Connection",
+    "Keep-Alive",
+    "Accept-Ranges",
+    "Date",
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum HttpError {
+    #[error("truncated {0}")]
+    Truncated(&'static str),
+    #[error("malformed {0}")]
+    Malformed(&'static str),
+}
+
+/// The start line and headers of a request or response. The body, if any,
+/// is ignored.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HttpMessage {
+    /// [`Section::HttpRequest`] or [`Section::HttpResponse`].
+    pub section: Section,
+    /// [`HttpVersion::V10`] or [`HttpVersion::V11`].
+    pub version: HttpVersion,
+    /// Names and values in the order they were sent, with values trimmed.
+    pub headers: Vec<(String, String)>,
+}
+
+impl HttpMessage {
+    /// Parses everything up to the empty line that ends the headers, which
+    /// has to be there already.
+    pub fn parse(message: &[u8]) -> Result<Self, HttpError> {
+        let end = (0..message.len())
+            .find_map(|i| match &message[i..] {
+                [b'\n', b'\n', ..] => Some(i + 2),
+                [b'\n', b'\r', b'\n', ..] => Some(i + 3),
+                _ => None,
+            })
+            .ok_or(HttpError::Truncated("http headers"))?;
+        let head = String::from_utf8_lossy(&message[..end]);
+        let mut lines = head.lines();
+
+        let start = lines.next().ok_or(HttpError::Malformed("start line"))?;
+        let (section, version) = match start.split_once(' ') {
+            Some((version, _)) if version.starts_with("HTTP/") => (Section::HttpResponse, version),
+            _ => (
+                Section::HttpRequest,
+                start
+                    .rsplit_once(' ')
+                    .ok_or(HttpError::Malformed("request line"))?
+                    .1,
+            ),
+        };
+        let version = match version {
+            "HTTP/1.0" => HttpVersion::V10,
+            "HTTP/1.1" => HttpVersion::V11,
+            _ => return Err(HttpError::Malformed("http version")),
+        };
+
+        let mut headers = Vec::<(String, String)>::new();
+        for line in lines.take_while(|line| !line.is_empty()) {
+            // A folded line continues the value of the header before it.
+            if line.starts_with([' ', '\t']) {
+                let (_, value) = headers
+                    .last_mut()
+                    .ok_or(HttpError::Malformed("header continuation"))?;
+                value.push(' ');
+                value.push_str(line.trim());
+                continue;
+            }
+
+            let (name, value) = line.split_once(':').ok_or(HttpError::Malformed("header"))?;
+            if name.is_empty() || name.contains([' ', '\t']) {
+                return Err(HttpError::Malformed("header name"));
+            }
+            headers.push((name.to_string(), value.trim().to_string()));
+        }
+
+        Ok(HttpMessage {
+            section,
+            version,
+            headers,
+        })
+    }
+
+    pub fn is_request(&self) -> bool {
+        self.section == Section::HttpRequest
+    }
+
+    /// The value of the first header called `name`, in any case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// `User-Agent` of a request, `Server` of a response.
+    pub fn software(&self) -> Option<&str> {
+        self.header(match self.is_request() {
+            true => "User-Agent",
+            false => "Server",
+        })
+    }
+
+    /// The language p0f reports for a request, e.g. `English` for an
+    /// `Accept-Language` of `en-US,en;q=0.5`. Only the first language counts,
+    /// and only by its two letter code.
+    pub fn language(&self) -> Option<&'static str> {
+        if !self.is_request() {
+            return None;
+        }
+
+        let code = self.header("Accept-Language")?.trim_start().as_bytes();
+        match code {
+            [a, b, rest @ ..]
+                if a.is_ascii_alphabetic()
+                    && b.is_ascii_alphabetic()
+                    && !rest.first().is_some_and(u8::is_ascii_alphabetic) =>
+            {
+                let code = [a.to_ascii_lowercase(), b.to_ascii_lowercase()];
+                language::LANGUAGES
+                    .iter()
+                    .find(|(known, _)| known.as_bytes() == code)
+                    .map(|(_, name)| *name)
+            }
+            _ => None,
+        }
+    }
+
+    /// The signature as p0f prints it in its `raw_sig`.
+    pub fn signature(&self) -> HttpSignature {
+        let (skip_value, common) = match self.is_request() {
+            true => (&REQUEST_SKIP_VALUE[..], &REQUEST_COMMON[..]),
+            false => (&RESPONSE_SKIP_VALUE[..], &RESPONSE_COMMON[..]),
+        };
+
+        HttpSignature {
+            version: self.version,
+            horder: self
+                .headers
+                .iter()
+                .map(|(name, value)| {
+                    let optional = self.is_optional(name);
+                    Header {
+                        name: name.clone(),
+                        value: (!optional && !contains(skip_value, name)).then(|| clean(value)),
+                        optional,
+                    }
+                })
+                .collect(),
+            habsent: common
+                .iter()
+                .filter(|name| self.header(name).is_none())
+                .map(|name| name.to_string())
+                .collect(),
+            expsw: self.software().map(clean).unwrap_or_default(),
+        }
+    }
+
+    /// Whether a header may be left out of a signature, even though it is in
+    /// the message.
+    pub(crate) fn is_optional(&self, name: &str) -> bool {
+        match self.is_request() {
+            true => contains(&REQUEST_OPTIONAL, name),
+            false => contains(&RESPONSE_OPTIONAL, name),
+        }
+    }
+}
+
+fn contains(names: &[&str], name: &str) -> bool {
+    names.iter().any(|known| known.eq_ignore_ascii_case(name))
+}
+
+/// Masks what wouldn't survive in a signature, like p0f does.
+fn clean(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != ']' => c,
+            _ => '?',
+        })
+        .collect()
+}
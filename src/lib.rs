@@ -3,301 +3,398 @@ use std::{
     io::{self, Read as _, Write as _},
     net::IpAddr,
     os::unix::net::UnixStream,
-    path::Path,
-    time::Duration,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
 };
 
+use builder::Timeouts;
 use chrono::{DateTime, Utc};
+use protocol::{Request, ResponseLayout, PROBE_SIZE, REQUEST_SIZE, RESPONSE_SIZE_MTU};
 use thiserror::Error;
 
-const REQUEST_MAGIC: u32 = 0x50304601;
-const RESPONSE_MAGIC: u32 = 0x50304602;
-
-const REQUEST_SIZE: usize = 21;
-#[cfg(not(feature = "p0f-mtu"))]
-const RESPONSE_SIZE: usize = 232;
-#[cfg(feature = "p0f-mtu")]
-const RESPONSE_SIZE: usize = 234;
-
-const STR_MAX: usize = 31;
-const STR_SIZE: usize = STR_MAX + 1;
-
-const STATUS_BADQUERY: u32 = 0x00;
-const STATUS_OK: u32 = 0x10;
-const STATUS_NOMATCH: u32 = 0x20;
-
-const ADDRESS_IPV4: u8 = 0x04;
-const ADDRESS_IPV6: u8 = 0x06;
-
-const MATCH_NORMAL: u8 = 0x00;
-const MATCH_FUZZY: u8 = 0x01;
-const MATCH_GENERIC: u8 = 0x02;
-const MATCH_FUZZY_GENERIC: u8 = 0x03;
+#[cfg(feature = "tokio")]
+mod asynchronous;
+mod builder;
+pub mod fingerprint;
+pub mod fpdb;
+pub mod hosts;
+pub mod http;
+mod listener;
+pub mod log;
+pub mod os;
+pub mod packet;
+pub mod pcap;
+mod pool;
+pub mod protocol;
+pub mod record;
+mod retry;
+pub mod server;
+pub mod signature;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod uptime;
+pub mod useragent;
+
+#[cfg(feature = "tokio")]
+pub use asynchronous::AsyncP0f;
+pub use builder::P0fBuilder;
+pub use pool::{P0fPool, PoolConfig, PoolStats, PooledP0f};
+pub use retry::RetryPolicy;
 
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("io error: {0}")]
-    Io(#[from] io::Error),
+    Io(io::Error),
+    #[error("timed out")]
+    Timeout,
     #[error("invalid magic")]
     InvalidMagic,
     #[error("bad query")]
     BadQuery,
+    #[error("no match")]
+    NoMatch,
     #[error("timestamp out of range: {0}")]
     TimestampOutOfRange(&'static str),
     #[error("missing data: {0}")]
     MissingData(&'static str),
     #[error("invalid data: {0}")]
     InvalidData(#[from] TryFromSliceError),
+    #[error("unknown status: {0:#x}")]
+    UnknownStatus(u32),
+    #[error("unknown value for {field}: {value}")]
+    UnknownEnumValue { field: &'static str, value: u8 },
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            // Sockets with a read or write timeout report it as one of these,
+            // depending on the platform.
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Error::Timeout,
+            _ => Error::Io(err),
+        }
+    }
 }
 
-pub struct P0f(UnixStream);
+impl Error {
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Io(err) => retry::TRANSIENT_ERROR_KINDS.contains(&err.kind()),
+            Error::Timeout => true,
+            _ => false,
+        }
+    }
+
+    fn breaks_connection(&self) -> bool {
+        !matches!(self, Error::BadQuery)
+    }
+}
+
+// Requests `P0f::query_many` writes ahead of the responses. Their responses
+// fit in the socket buffers with room to spare, so neither side ever blocks
+// on writing while the other does too.
+const MAX_IN_FLIGHT: usize = 64;
+
+pub struct P0f {
+    path: PathBuf,
+    stream: Option<UnixStream>,
+    timeouts: Timeouts,
+    retry: RetryPolicy,
+    decode_mode: DecodeMode,
+    layout: ResponseLayout,
+    resolved_layout: Option<ResponseLayout>,
+}
 
 impl P0f {
     pub fn new<T: AsRef<Path>>(path: T) -> io::Result<Self> {
-        let socket = UnixStream::connect(path)?;
+        let path = path.as_ref().to_path_buf();
+        let socket = UnixStream::connect(&path)?;
+
+        Ok(P0f {
+            path,
+            stream: Some(socket),
+            timeouts: Timeouts::default(),
+            retry: RetryPolicy::default(),
+            decode_mode: DecodeMode::default(),
+            layout: ResponseLayout::default(),
+            resolved_layout: None,
+        })
+    }
+
+    pub fn builder<T: AsRef<Path>>(path: T) -> P0fBuilder {
+        P0fBuilder::new(path)
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry
+    }
 
-        Ok(P0f(socket))
+    pub fn with_decode_mode(mut self, decode_mode: DecodeMode) -> Self {
+        self.decode_mode = decode_mode;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The layout in use, or [`ResponseLayout::Auto`] if it hasn't been
+    /// probed yet.
+    pub fn layout(&self) -> ResponseLayout {
+        self.resolved_layout.unwrap_or(self.layout)
     }
 
     pub fn query<T: Into<IpAddr>>(&mut self, address: T) -> Result<Option<Response>, Error> {
-        let address = address.into();
+        let response = self.query_raw_until(address.into(), None)?;
+        self.decode(&response)
+    }
 
-        let mut request = Vec::with_capacity(REQUEST_SIZE);
-        request.extend_from_slice(&REQUEST_MAGIC.to_ne_bytes());
+    pub fn query_with_deadline<T: Into<IpAddr>>(
+        &mut self,
+        address: T,
+        deadline: Instant,
+    ) -> Result<Option<Response>, Error> {
+        let response = self.query_raw_until(address.into(), Some(deadline))?;
+        self.decode(&response)
+    }
 
-        match address {
-            IpAddr::V4(address) => {
-                request.push(ADDRESS_IPV4);
-                request.extend_from_slice(&address.octets());
-                request.extend_from_slice(&[0; 12]);
+    /// Like [`P0f::query`], but hands back the response exactly as the daemon
+    /// sent it, without decoding it.
+    pub fn query_raw<T: Into<IpAddr>>(&mut self, address: T) -> Result<Vec<u8>, Error> {
+        self.query_raw_until(address.into(), None)
+    }
+
+    /// Queries every address over the one connection, with up to 64 requests
+    /// written ahead of the responses, and returns
+    /// the results in the same order.
+    ///
+    /// This relies on p0f answering the queries on a connection strictly in
+    /// order: it reads a single query at a time, and doesn't read the next
+    /// one until it has written the whole response to the last. The requests
+    /// written ahead wait in the socket buffer in the meantime, which is also
+    /// why their number is bounded.
+    ///
+    /// If a request can't be written, the ones written before it still get
+    /// their responses, and it is sent again over a new connection along with
+    /// the rest. If reading a response fails, only its address gets the
+    /// error, and the ones after it are sent again over a new connection. The
+    /// retry policy doesn't apply.
+    pub fn query_many<I>(&mut self, addresses: I) -> Vec<Result<Option<Response>, Error>>
+    where
+        I: IntoIterator<Item = IpAddr>,
+    {
+        let addresses = addresses.into_iter().collect::<Vec<_>>();
+        let mut results = Vec::with_capacity(addresses.len());
+        let mut sent = 0;
+        let mut draining = false;
+
+        while results.len() < addresses.len() {
+            let result = self
+                .pipeline(&addresses, &mut sent, results.len(), &mut draining)
+                .and_then(|response| {
+                    protocol::decode_response(&response, ResponseLayout::Auto, self.decode_mode)
+                });
+            if let Err(err) = &result {
+                if err.breaks_connection() {
+                    self.disconnect();
+                    sent = results.len() + 1;
+                    draining = false;
+                }
             }
-            IpAddr::V6(address) => {
-                request.push(ADDRESS_IPV6);
-                request.extend_from_slice(&address.octets());
+
+            results.push(result);
+        }
+
+        results
+    }
+
+    pub(crate) fn decode(&mut self, response: &[u8]) -> Result<Option<Response>, Error> {
+        let result = protocol::decode_response(response, ResponseLayout::Auto, self.decode_mode);
+        if let Err(err) = &result {
+            if err.breaks_connection() {
+                self.disconnect();
             }
         }
 
-        self.0.write_all(&request)?;
-        let mut response = [0; RESPONSE_SIZE];
-        self.0.read_exact(&mut response)?;
-        let mut response = BufferReader::new(&response);
+        result
+    }
 
-        let magic = u32::from_ne_bytes(*response.read_array().ok_or(Error::MissingData("magic"))?);
-        if magic != RESPONSE_MAGIC {
-            return Err(Error::InvalidMagic);
+    fn query_raw_until(
+        &mut self,
+        address: IpAddr,
+        deadline: Option<Instant>,
+    ) -> Result<Vec<u8>, Error> {
+        let request = Request::new(address).encode();
+
+        let mut attempt = 1;
+        loop {
+            match self.exchange(&request, deadline) {
+                Err(err) if attempt < self.retry.max_attempts && self.retry.is_transient(&err) => {
+                    let backoff = self.retry.backoff(attempt);
+                    if deadline.is_some_and(|deadline| Instant::now() + backoff >= deadline) {
+                        return Err(Error::Timeout);
+                    }
+
+                    thread::sleep(backoff);
+                    attempt += 1;
+                }
+                result => return result,
+            }
         }
-        let status =
-            u32::from_ne_bytes(*response.read_array().ok_or(Error::MissingData("status"))?);
-        match status {
-            STATUS_BADQUERY => return Err(Error::BadQuery),
-            STATUS_OK => {}
-            STATUS_NOMATCH => return Ok(None),
-            _ => unreachable!(),
+    }
+
+    fn exchange(
+        &mut self,
+        request: &[u8; REQUEST_SIZE],
+        deadline: Option<Instant>,
+    ) -> Result<Vec<u8>, Error> {
+        let result = self.try_exchange(request, deadline);
+        if let Err(err) = &result {
+            if err.breaks_connection() {
+                self.disconnect();
+            }
         }
 
-        let first_seen = DateTime::from_timestamp(
-            u32::from_ne_bytes(
-                *response
-                    .read_array()
-                    .ok_or(Error::MissingData("first_seen"))?,
-            ) as i64,
-            0,
-        )
-        .ok_or(Error::TimestampOutOfRange("first_seen"))?;
-        let last_seen = DateTime::from_timestamp(
-            u32::from_ne_bytes(
-                *response
-                    .read_array()
-                    .ok_or(Error::MissingData("last_seen"))?,
-            ) as i64,
-            0,
-        )
-        .ok_or(Error::TimestampOutOfRange("last_seen"))?;
-        let total_conn = u32::from_ne_bytes(
-            *response
-                .read_array()
-                .ok_or(Error::MissingData("total_conn"))?,
-        );
-
-        let uptime_min = match u32::from_ne_bytes(
-            *response
-                .read_array()
-                .ok_or(Error::MissingData("uptime_min"))?,
-        ) {
-            0 => None,
-            uptime => Some(Duration::from_secs(uptime as u64 * 60)),
-        };
-        let up_mod_days = Duration::from_secs(
-            u32::from_ne_bytes(
-                *response
-                    .read_array()
-                    .ok_or(Error::MissingData("up_mod_days"))?,
-            ) as u64
-                * 86400,
-        );
-
-        let last_nat = match u32::from_ne_bytes(
-            *response
-                .read_array()
-                .ok_or(Error::MissingData("last_nat"))?,
-        ) {
-            0 => None,
-            last_nat => Some(
-                DateTime::from_timestamp(last_nat as i64, 0)
-                    .ok_or(Error::TimestampOutOfRange("last_seen"))?,
-            ),
-        };
+        result
+    }
 
-        let last_chg = match u32::from_ne_bytes(
-            *response
-                .read_array()
-                .ok_or(Error::MissingData("last_chg"))?,
-        ) {
-            0 => None,
-            last_chg => Some(
-                DateTime::from_timestamp(last_chg as i64, 0)
-                    .ok_or(Error::TimestampOutOfRange("last_chg"))?,
-            ),
-        };
-        let distance = match i16::from_ne_bytes(
-            *response
-                .read_array()
-                .ok_or(Error::MissingData("distance"))?,
-        ) {
-            -1 => None,
-            distance => Some(distance),
-        };
+    fn try_exchange(
+        &mut self,
+        request: &[u8; REQUEST_SIZE],
+        deadline: Option<Instant>,
+    ) -> Result<Vec<u8>, Error> {
+        let layout = self.resolve_layout(deadline)?;
+        let stream = self
+            .stream
+            .as_mut()
+            .ok_or(Error::Io(io::ErrorKind::NotConnected.into()))?;
+
+        send(stream, request, &self.timeouts, deadline)?;
+        let mut response = vec![0; layout.size().unwrap_or(RESPONSE_SIZE_MTU)];
+        recv(stream, &mut response, &self.timeouts, deadline)?;
+
+        Ok(response)
+    }
 
-        let bad_sw =
-            match u8::from_ne_bytes(*response.read_array().ok_or(Error::MissingData("bad_sw"))?) {
-                0 => None,
-                1 => Some(BadSw::OsDifference),
-                2 => Some(BadSw::OutrightMismatch),
-                d => {
-                    println!("bad_sw: {}", d);
-                    unreachable!();
+    /// Tops up the requests in flight and reads the response to the oldest
+    /// of them, `addresses[answered]`.
+    ///
+    /// Once a request can't be written, `draining` is set and nothing more is
+    /// written; the connection is dropped after the last response to what
+    /// was written before, so that the rest goes out over a new one.
+    fn pipeline(
+        &mut self,
+        addresses: &[IpAddr],
+        sent: &mut usize,
+        answered: usize,
+        draining: &mut bool,
+    ) -> Result<Vec<u8>, Error> {
+        let layout = self.resolve_layout(None)?;
+        let stream = self
+            .stream
+            .as_mut()
+            .ok_or(Error::Io(io::ErrorKind::NotConnected.into()))?;
+
+        if !*draining {
+            let end = addresses.len().min(answered + MAX_IN_FLIGHT);
+            for &address in &addresses[*sent..end] {
+                if let Err(err) = send(
+                    stream,
+                    &Request::new(address).encode(),
+                    &self.timeouts,
+                    None,
+                ) {
+                    // With nothing in flight, this is the request that failed.
+                    if *sent == answered {
+                        return Err(err);
+                    }
+                    *draining = true;
+                    break;
                 }
-            };
-        let os_match_q = match u8::from_ne_bytes(
-            *response
-                .read_array()
-                .ok_or(Error::MissingData("os_match_q"))?,
-        ) {
-            MATCH_NORMAL => OsMatchQuality::Normal,
-            MATCH_FUZZY => OsMatchQuality::Fuzzy,
-            MATCH_GENERIC => OsMatchQuality::Generic,
-            MATCH_FUZZY_GENERIC => OsMatchQuality::FuzzyGeneric,
-            _ => unreachable!(),
-        };
+                *sent += 1;
+            }
+        }
 
-        let os_name = match response.get_buffer()[0] {
-            0 => None,
-            _ => Some(
-                String::from_utf8_lossy(
-                    &response
-                        .read_array::<STR_SIZE>()
-                        .ok_or(Error::MissingData("os_name"))?[..STR_SIZE],
-                )
-                .trim_end_matches('\0')
-                .to_string(),
-            ),
-        };
+        let mut response = vec![0; layout.size().unwrap_or(RESPONSE_SIZE_MTU)];
+        recv(stream, &mut response, &self.timeouts, None)?;
 
-        let os_flavor = match response.get_buffer()[0] {
-            0 => None,
-            _ => Some(
-                String::from_utf8_lossy(
-                    &response
-                        .read_array::<STR_SIZE>()
-                        .ok_or(Error::MissingData("os_flavor"))?[..STR_SIZE],
-                )
-                .trim_end_matches('\0')
-                .to_string(),
-            ),
-        };
+        if *draining && answered + 1 == *sent {
+            self.disconnect();
+            *draining = false;
+        }
 
-        let http_name = match response.get_buffer()[0] {
-            0 => None,
-            _ => Some(
-                String::from_utf8_lossy(
-                    &response
-                        .read_array::<STR_SIZE>()
-                        .ok_or(Error::MissingData("http_name"))?[..STR_SIZE],
-                )
-                .trim_end_matches('\0')
-                .to_string(),
-            ),
-        };
+        Ok(response)
+    }
 
-        let http_flavor = match response.get_buffer()[0] {
-            0 => None,
-            _ => Some(
-                String::from_utf8_lossy(
-                    &response
-                        .read_array::<STR_SIZE>()
-                        .ok_or(Error::MissingData("http_flavor"))?[..STR_SIZE],
-                )
-                .trim_end_matches('\0')
-                .to_string(),
-            ),
-        };
+    fn disconnect(&mut self) {
+        self.stream = None;
+        self.resolved_layout = None;
+    }
 
-        #[cfg(feature = "p0f-mtu")]
-        let link_mtu = u16::from_ne_bytes(
-            *response
-                .read_array()
-                .ok_or(Error::MissingData("mtu"))?,
-        );
-
-        let link_type = match response.get_buffer()[0] {
-            0 => None,
-            _ => Some(
-                String::from_utf8_lossy(
-                    &response
-                        .read_array::<STR_SIZE>()
-                        .ok_or(Error::MissingData("link_type"))?[..STR_SIZE],
-                )
-                .trim_end_matches('\0')
-                .to_string(),
-            ),
+    fn resolve_layout(&mut self, deadline: Option<Instant>) -> Result<ResponseLayout, Error> {
+        let stream = match &mut self.stream {
+            Some(stream) => stream,
+            None => self
+                .stream
+                .insert(builder::connect(&self.path, &self.timeouts, deadline)?),
         };
 
-        let language = match response.get_buffer()[0] {
-            0 => None,
-            _ => Some(
-                String::from_utf8_lossy(
-                    &response
-                        .read_array::<STR_SIZE>()
-                        .ok_or(Error::MissingData("language"))?[..STR_SIZE],
-                )
-                .trim_end_matches('\0')
-                .to_string(),
-            ),
-        };
+        if let Some(layout) = self.resolved_layout {
+            return Ok(layout);
+        }
+        if self.layout != ResponseLayout::Auto {
+            return Ok(self.layout);
+        }
 
-        Ok(Some(Response {
-            first_seen,
-            last_seen,
-            total_conn,
-            uptime_min,
-            up_mod_days,
-            last_nat,
-            last_chg,
-            distance,
-            bad_sw,
-            os_match_q,
-            os_name,
-            os_flavor,
-            http_name,
-            http_flavor,
-            #[cfg(feature = "p0f-mtu")]
-            link_mtu,
-            link_type,
-            language,
-        }))
+        send(stream, &protocol::probe_request(), &self.timeouts, deadline)?;
+        let mut probe = [0; PROBE_SIZE];
+        recv(stream, &mut probe, &self.timeouts, deadline)?;
+        let layout = ResponseLayout::from_probe(&probe)?;
+
+        // Throw away whatever is left of both probe responses.
+        let mut rest = [0; 2 * RESPONSE_SIZE_MTU - PROBE_SIZE];
+        let size = layout.size().unwrap_or(RESPONSE_SIZE_MTU);
+        recv(
+            stream,
+            &mut rest[..2 * size - PROBE_SIZE],
+            &self.timeouts,
+            deadline,
+        )?;
+
+        Ok(*self.resolved_layout.insert(layout))
     }
 }
 
+fn send(
+    stream: &mut UnixStream,
+    buffer: &[u8],
+    timeouts: &Timeouts,
+    deadline: Option<Instant>,
+) -> Result<(), Error> {
+    stream.set_write_timeout(builder::remaining(timeouts.write, deadline)?)?;
+    stream.write_all(buffer)?;
+
+    Ok(())
+}
+
+fn recv(
+    stream: &mut UnixStream,
+    buffer: &mut [u8],
+    timeouts: &Timeouts,
+    deadline: Option<Instant>,
+) -> Result<(), Error> {
+    stream.set_read_timeout(builder::remaining(timeouts.read, deadline)?)?;
+    stream.read_exact(buffer)?;
+
+    Ok(())
+}
+
 #[derive(Clone, Debug)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Response {
@@ -315,17 +412,45 @@ pub struct Response {
     pub os_flavor: Option<String>,
     pub http_name: Option<String>,
     pub http_flavor: Option<String>,
-    #[cfg(feature = "p0f-mtu")]
-    pub link_mtu: u16,
+    pub link_mtu: Option<u16>,
     pub link_type: Option<String>,
     pub language: Option<String>,
 }
 
-#[derive(Clone, Debug)]
+#[cfg(test)]
+impl Response {
+    /// A plausible response that tells which address it is for by its
+    /// `os_name`.
+    pub(crate) fn for_address(address: IpAddr) -> Response {
+        Response {
+            first_seen: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            last_seen: DateTime::from_timestamp(1_700_000_600, 0).unwrap(),
+            total_conn: 3,
+            uptime_min: Some(Duration::from_secs(90 * 60)),
+            up_mod_days: Duration::from_secs(49 * 86400),
+            last_nat: None,
+            last_chg: None,
+            distance: Some(8),
+            bad_sw: None,
+            os_match_q: OsMatchQuality::Normal,
+            os_name: Some(address.to_string()),
+            os_flavor: Some("3.x".to_string()),
+            http_name: None,
+            http_flavor: None,
+            link_mtu: None,
+            link_type: Some("Ethernet or modem".to_string()),
+            language: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BadSw {
     OsDifference,
     OutrightMismatch,
+    /// Only produced by [`DecodeMode::Lenient`].
+    Unknown(u8),
 }
 
 #[derive(Clone, Debug)]
@@ -335,30 +460,98 @@ pub enum OsMatchQuality {
     Fuzzy,
     Generic,
     FuzzyGeneric,
+    /// Only produced by [`DecodeMode::Lenient`].
+    Unknown(u8),
 }
 
-struct BufferReader<'a> {
-    buffer: &'a [u8],
-    pos: usize,
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DecodeMode {
+    /// Reject enum values this crate doesn't know about.
+    #[default]
+    Strict,
+    /// Keep unknown enum values around as `Unknown(_)` variants, e.g. when
+    /// talking to a newer p0f build.
+    Lenient,
 }
 
-impl<'a> BufferReader<'a> {
-    fn new(buffer: &'a [u8]) -> Self {
-        BufferReader { buffer, pos: 0 }
+impl DecodeMode {
+    fn check(self, field: &'static str, value: u8) -> Result<u8, Error> {
+        match self {
+            DecodeMode::Strict => Err(Error::UnknownEnumValue { field, value }),
+            DecodeMode::Lenient => Ok(value),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::testing::{Fault, MockP0fServer};
+
+    fn addresses(count: u16) -> Vec<IpAddr> {
+        (0..count)
+            .map(|i| Ipv4Addr::new(10, 0, (i >> 8) as u8, i as u8).into())
+            .collect()
+    }
+
+    fn server() -> MockP0fServer {
+        MockP0fServer::from_fn(|address| Some(Response::for_address(address))).unwrap()
     }
 
-    fn read_array<const N: usize>(&mut self) -> Option<&'a [u8; N]> {
-        if self.pos + N <= self.buffer.len() {
-            let slice = &self.buffer[self.pos..self.pos + N];
-            self.pos += N;
-            // SAFETY: We know that the slice has exactly N elements
-            Some(slice.try_into().unwrap())
-        } else {
-            None
+    fn assert_answers(address: IpAddr, result: &Result<Option<Response>, Error>) {
+        match result {
+            Ok(Some(response)) => assert_eq!(response.os_name, Some(address.to_string())),
+            result => panic!("{address}: {result:?}"),
         }
     }
 
-    fn get_buffer(&self) -> &'a [u8] {
-        &self.buffer[self.pos..]
+    #[test]
+    fn query_many() {
+        let server = server();
+        let mut p0f = P0f::new(server.path()).unwrap();
+        let addresses = addresses(3 * MAX_IN_FLIGHT as u16 + 10);
+
+        let results = p0f.query_many(addresses.clone());
+        assert_eq!(results.len(), addresses.len());
+        for (address, result) in addresses.iter().zip(&results) {
+            assert_answers(*address, result);
+        }
+        assert_eq!(server.requests(), addresses);
+    }
+
+    #[test]
+    fn query_many_fault() {
+        let server = server();
+        let mut p0f = P0f::new(server.path()).unwrap();
+        let addresses = addresses(2 * MAX_IN_FLIGHT as u16 + 10);
+        let (bad_query, dropped) = (addresses[10], addresses[MAX_IN_FLIGHT + 5]);
+
+        server.fail_with(move |address| match address {
+            address if address == bad_query => Some(Fault::BadQuery),
+            address if address == dropped => Some(Fault::DropMidResponse),
+            _ => None,
+        });
+        let results = p0f.query_many(addresses.clone());
+
+        for (address, result) in addresses.iter().zip(&results) {
+            let fault = match *address {
+                address if address == bad_query => Fault::BadQuery,
+                address if address == dropped => Fault::DropMidResponse,
+                address => {
+                    assert_answers(address, result);
+                    continue;
+                }
+            };
+            let err = result.as_ref().unwrap_err();
+            assert!(fault.is_expected(err), "{address}: {err:?}");
+        }
+        // Whatever was written ahead of the dropped response goes out again,
+        // and nothing twice.
+        assert_eq!(server.requests(), addresses);
+
+        server.clear_faults();
+        assert_answers(dropped, &p0f.query(dropped));
     }
 }
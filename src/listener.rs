@@ -0,0 +1,131 @@
+//! The accept loop behind [`P0fServer`](crate::server::P0fServer) and the
+//! mock server of the `testing` feature. Every connection is served from a
+//! thread of its own, and all of them are closed on shutdown.
+
+use std::{
+    collections::HashMap,
+    net::Shutdown,
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+};
+
+pub(crate) struct Listener {
+    path: PathBuf,
+    shared: Arc<Shared>,
+    accept: Option<JoinHandle<()>>,
+}
+
+struct Shared {
+    max_conn: usize,
+    connections: Mutex<Connections>,
+    shutdown: AtomicBool,
+}
+
+#[derive(Default)]
+struct Connections {
+    open: HashMap<u64, UnixStream>,
+    next_id: u64,
+    rejected: u64,
+}
+
+impl Listener {
+    /// Serves the connections accepted on `listener`, which is bound to
+    /// `path`, with `serve`. Connections beyond `max_conn` are accepted and
+    /// closed right away.
+    pub(crate) fn spawn<F>(listener: UnixListener, path: PathBuf, max_conn: usize, serve: F) -> Self
+    where
+        F: Fn(UnixStream) + Send + Sync + 'static,
+    {
+        let shared = Arc::new(Shared {
+            max_conn,
+            connections: Mutex::new(Connections::default()),
+            shutdown: AtomicBool::new(false),
+        });
+
+        let accept = {
+            let shared = shared.clone();
+            thread::spawn(move || accept(listener, shared, Arc::new(serve)))
+        };
+
+        Listener {
+            path,
+            shared,
+            accept: Some(accept),
+        }
+    }
+
+    pub(crate) fn connections(&self) -> usize {
+        lock(&self.shared.connections).open.len()
+    }
+
+    pub(crate) fn rejected(&self) -> u64 {
+        lock(&self.shared.connections).rejected
+    }
+
+    /// Stops accepting connections and closes the open ones, without
+    /// waiting for their threads to finish.
+    pub(crate) fn shutdown(&mut self) {
+        let Some(accept) = self.accept.take() else {
+            return;
+        };
+
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        // Wake up the accept loop so it notices the shutdown flag.
+        let _ = UnixStream::connect(&self.path);
+        let _ = accept.join();
+
+        for stream in lock(&self.shared.connections).open.values() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn accept<F>(listener: UnixListener, shared: Arc<Shared>, serve: Arc<F>)
+where
+    F: Fn(UnixStream) + Send + Sync + 'static,
+{
+    for stream in listener.incoming() {
+        if shared.shutdown.load(Ordering::SeqCst) {
+            break;
+        }
+        let Ok(stream) = stream else {
+            continue;
+        };
+
+        let mut connections = lock(&shared.connections);
+        if connections.open.len() >= shared.max_conn {
+            connections.rejected += 1;
+            continue;
+        }
+        let Ok(handle) = stream.try_clone() else {
+            continue;
+        };
+
+        let id = connections.next_id;
+        connections.next_id += 1;
+        connections.open.insert(id, handle);
+        drop(connections);
+
+        let shared = shared.clone();
+        let serve = serve.clone();
+        thread::spawn(move || {
+            serve(stream);
+            lock(&shared.connections).open.remove(&id);
+        });
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
@@ -0,0 +1,499 @@
+//! Parser for the log p0f writes with `-o`, one observation per line:
+//!
+//! ```text
+//! [2024/01/01 12:00:00] mod=syn|cli=1.2.3.4/1234|srv=4.3.2.1/80|subj=cli|os=Linux 3.11 and newer|dist=0|params=none|raw_sig=4:64+0:0:1460:mss*20,7:mss,sok,ts,nop,ws:df,id+:0
+//! ```
+//!
+//! p0f writes the time in the local time zone of the host it runs on, so it
+//! is kept as a [`NaiveDateTime`]. [`LogReader`] reads a log as it is,
+//! [`LogTailer`] follows one as p0f writes to it.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    net::SocketAddr,
+    path::Path,
+    str::FromStr,
+    time::Duration,
+};
+
+use chrono::NaiveDateTime;
+
+use crate::signature::{HttpSignature, TcpSignature};
+
+#[cfg(feature = "tokio")]
+mod asynchronous;
+mod tail;
+
+#[cfg(feature = "tokio")]
+pub use asynchronous::AsyncLogTailer;
+pub use tail::{LogTailer, TailConfig};
+
+const TIME_FORMAT: &str = "%Y/%m/%d %H:%M:%S";
+// What p0f writes for a field it has no value for.
+const UNKNOWN: &str = "???";
+
+#[derive(Debug, thiserror::Error)]
+pub enum LogError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("line {line}: {reason}")]
+    Malformed { line: usize, reason: &'static str },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogEntry {
+    pub time: NaiveDateTime,
+    pub client: SocketAddr,
+    pub server: SocketAddr,
+    /// The host the observation is about.
+    pub subject: Subject,
+    pub event: Event,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Subject {
+    Client,
+    Server,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// `mod=syn`
+    Syn(TcpEvent),
+    /// `mod=syn+ack`
+    SynAck(TcpEvent),
+    /// `mod=mtu`
+    Mtu {
+        /// `None` for an MTU that isn't in `p0f.fp`.
+        link: Option<String>,
+        raw_mtu: u16,
+    },
+    /// `mod=uptime`
+    Uptime {
+        uptime: Duration,
+        /// The TCP timestamp clock wraps around after this many days, so the
+        /// real uptime may be longer by any multiple of it.
+        modulo_days: u32,
+        /// Frequency of the TCP timestamp clock, in Hz.
+        raw_freq: f64,
+    },
+    /// `mod=http request`
+    HttpRequest(HttpEvent),
+    /// `mod=http response`
+    HttpResponse(HttpEvent),
+    /// `mod=host change`
+    HostChange(ChangeEvent),
+    /// `mod=ip sharing`
+    IpSharing(ChangeEvent),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TcpEvent {
+    /// Label name and flavor, separated by a space, or `None` if nothing in
+    /// `p0f.fp` matched.
+    pub os: Option<String>,
+    pub dist: i16,
+    /// Notes on the match, such as `generic` or `fuzzy`.
+    pub params: Vec<String>,
+    pub raw_sig: TcpSignature,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HttpEvent {
+    /// Label name and flavor, separated by a space, or `None` if nothing in
+    /// `p0f.fp` matched.
+    pub app: Option<String>,
+    pub lang: Option<String>,
+    /// Notes on the match, such as `dishonest` or `anonymous`.
+    pub params: Vec<String>,
+    pub raw_sig: HttpSignature,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChangeEvent {
+    /// What gave the change away, such as `tstamp` or `ttl`.
+    pub reason: Vec<String>,
+    pub raw_hits: Vec<u32>,
+}
+
+impl FromStr for LogEntry {
+    type Err = &'static str;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let line = line.strip_prefix('[').ok_or("missing time")?;
+        let (time, fields) = line.split_once("] ").ok_or("missing time")?;
+        let time = NaiveDateTime::parse_from_str(time, TIME_FORMAT).map_err(|_| "invalid time")?;
+
+        let mut module = None;
+        let mut client = None;
+        let mut server = None;
+        let mut subject = None;
+        let mut values = Vec::new();
+        for field in fields.split('|') {
+            let (key, value) = field.split_once('=').ok_or("expected key=value")?;
+            match key {
+                "mod" => module = Some(value),
+                "cli" => client = Some(address(value)?),
+                "srv" => server = Some(address(value)?),
+                "subj" => {
+                    subject = Some(match value {
+                        "cli" => Subject::Client,
+                        "srv" => Subject::Server,
+                        _ => return Err("subj must be cli or srv"),
+                    })
+                }
+                _ => values.push((key, value)),
+            }
+        }
+        let fields = Fields(values);
+
+        let event = match module.ok_or("missing mod")? {
+            "syn" => Event::Syn(TcpEvent::from_fields(&fields)?),
+            "syn+ack" => Event::SynAck(TcpEvent::from_fields(&fields)?),
+            "mtu" => Event::Mtu {
+                link: known(fields.get("link")?),
+                raw_mtu: fields.parse("raw_mtu", "invalid raw_mtu")?,
+            },
+            "uptime" => {
+                let (uptime, modulo_days) = uptime(fields.get("uptime")?)?;
+                let raw_freq = fields.get("raw_freq")?;
+                Event::Uptime {
+                    uptime,
+                    modulo_days,
+                    raw_freq: raw_freq
+                        .strip_suffix(" Hz")
+                        .unwrap_or(raw_freq)
+                        .parse()
+                        .map_err(|_| "invalid raw_freq")?,
+                }
+            }
+            "http request" => Event::HttpRequest(HttpEvent::from_fields(&fields)?),
+            "http response" => Event::HttpResponse(HttpEvent::from_fields(&fields)?),
+            "host change" => Event::HostChange(ChangeEvent::from_fields(&fields)?),
+            "ip sharing" => Event::IpSharing(ChangeEvent::from_fields(&fields)?),
+            _ => return Err("unknown mod"),
+        };
+
+        Ok(LogEntry {
+            time,
+            client: client.ok_or("missing cli")?,
+            server: server.ok_or("missing srv")?,
+            subject: subject.ok_or("missing subj")?,
+            event,
+        })
+    }
+}
+
+impl TcpEvent {
+    fn from_fields(fields: &Fields) -> Result<Self, &'static str> {
+        Ok(TcpEvent {
+            os: known(fields.get("os")?),
+            dist: fields.parse("dist", "invalid dist")?,
+            params: params(fields.get("params")?),
+            raw_sig: fields.get("raw_sig")?.parse()?,
+        })
+    }
+}
+
+impl HttpEvent {
+    fn from_fields(fields: &Fields) -> Result<Self, &'static str> {
+        Ok(HttpEvent {
+            app: known(fields.get("app")?),
+            lang: match fields.get("lang")? {
+                "none" => None,
+                lang => known(lang),
+            },
+            params: params(fields.get("params")?),
+            raw_sig: fields.get("raw_sig")?.parse()?,
+        })
+    }
+}
+
+impl ChangeEvent {
+    fn from_fields(fields: &Fields) -> Result<Self, &'static str> {
+        Ok(ChangeEvent {
+            reason: params(fields.get("reason")?),
+            raw_hits: fields
+                .get("raw_hits")?
+                .split(',')
+                .map(|hits| hits.parse().map_err(|_| "invalid raw_hits"))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+struct Fields<'a>(Vec<(&'a str, &'a str)>);
+
+impl<'a> Fields<'a> {
+    fn get(&self, key: &'static str) -> Result<&'a str, &'static str> {
+        self.0
+            .iter()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| *value)
+            .ok_or("missing field")
+    }
+
+    fn parse<T: FromStr>(&self, key: &'static str, err: &'static str) -> Result<T, &'static str> {
+        self.get(key)?.parse().map_err(|_| err)
+    }
+}
+
+/// Reads a log line by line, skipping empty lines.
+pub struct LogReader<R: BufRead> {
+    lines: io::Lines<R>,
+    line: usize,
+}
+
+impl<R: BufRead> LogReader<R> {
+    pub fn new(reader: R) -> Self {
+        LogReader {
+            lines: reader.lines(),
+            line: 0,
+        }
+    }
+}
+
+impl LogReader<BufReader<File>> {
+    pub fn open<T: AsRef<Path>>(path: T) -> io::Result<Self> {
+        Ok(Self::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+impl<R: BufRead> Iterator for LogReader<R> {
+    type Item = Result<LogEntry, LogError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(err) => return Some(Err(err.into())),
+            };
+            self.line += 1;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            return Some(line.parse().map_err(|reason| LogError::Malformed {
+                line: self.line,
+                reason,
+            }));
+        }
+    }
+}
+
+/// `1.2.3.4/1234` or `2001:db8::1/1234`.
+fn address(value: &str) -> Result<SocketAddr, &'static str> {
+    let (ip, port) = value.rsplit_once('/').ok_or("address must be ip/port")?;
+
+    Ok(SocketAddr::new(
+        ip.parse().map_err(|_| "invalid ip address")?,
+        port.parse().map_err(|_| "invalid port")?,
+    ))
+}
+
+fn known(value: &str) -> Option<String> {
+    (value != UNKNOWN).then(|| value.to_string())
+}
+
+fn params(value: &str) -> Vec<String> {
+    match value {
+        "none" => Vec::new(),
+        value => value.split_whitespace().map(str::to_string).collect(),
+    }
+}
+
+/// `11 days 4 hrs 16 min (modulo 198 days)`
+fn uptime(value: &str) -> Result<(Duration, u32), &'static str> {
+    let words = value.split_whitespace().collect::<Vec<_>>();
+    let ["(modulo", modulo, "days)"] = words[words.len().saturating_sub(3)..] else {
+        return Err("uptime is missing its modulo");
+    };
+
+    let mut minutes = 0u64;
+    for pair in words[..words.len() - 3].chunks(2) {
+        let [amount, unit] = pair else {
+            return Err("invalid uptime");
+        };
+        let amount = amount.parse::<u64>().map_err(|_| "invalid uptime")?;
+        let unit = match *unit {
+            "days" => 24 * 60,
+            "hrs" => 60,
+            "min" => 1,
+            _ => return Err("invalid uptime"),
+        };
+        minutes = amount
+            .checked_mul(unit)
+            .and_then(|amount| minutes.checked_add(amount))
+            .ok_or("invalid uptime")?;
+    }
+    let seconds = minutes.checked_mul(60).ok_or("invalid uptime")?;
+
+    Ok((
+        Duration::from_secs(seconds),
+        modulo.parse().map_err(|_| "invalid uptime modulo")?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> LogEntry {
+        line.parse().unwrap()
+    }
+
+    #[test]
+    fn syn() {
+        let entry = parse("[2024/01/01 12:00:00] mod=syn|cli=1.2.3.4/1234|srv=4.3.2.1/80|subj=cli|os=Linux 3.11 and newer|dist=0|params=none|raw_sig=4:64+0:0:1460:mss*20,7:mss,sok,ts,nop,ws:df,id+:0");
+
+        assert_eq!(entry.time.to_string(), "2024-01-01 12:00:00");
+        assert_eq!(entry.client, "1.2.3.4:1234".parse().unwrap());
+        assert_eq!(entry.server, "4.3.2.1:80".parse().unwrap());
+        assert_eq!(entry.subject, Subject::Client);
+        let Event::Syn(event) = entry.event else {
+            panic!("{:?}", entry.event);
+        };
+        assert_eq!(event.os.as_deref(), Some("Linux 3.11 and newer"));
+        assert_eq!(event.dist, 0);
+        assert!(event.params.is_empty());
+        assert_eq!(
+            event.raw_sig.to_string(),
+            "4:64+0:0:1460:mss*20,7:mss,sok,ts,nop,ws:df,id+:0"
+        );
+    }
+
+    #[test]
+    fn syn_ack() {
+        let entry = parse("[2024/01/01 12:00:00] mod=syn+ack|cli=2001:db8::1/1234|srv=2001:db8::2/443|subj=srv|os=???|dist=11|params=generic fuzzy|raw_sig=6:53+11:0:1440:65535,6:mss,nop,ws,sok,ts:flow:0");
+
+        assert_eq!(entry.client, "[2001:db8::1]:1234".parse().unwrap());
+        assert_eq!(entry.subject, Subject::Server);
+        let Event::SynAck(event) = entry.event else {
+            panic!("{:?}", entry.event);
+        };
+        assert_eq!(event.os, None);
+        assert_eq!(event.dist, 11);
+        assert_eq!(event.params, ["generic", "fuzzy"]);
+    }
+
+    #[test]
+    fn mtu() {
+        let entry = parse("[2024/01/01 12:00:00] mod=mtu|cli=1.2.3.4/1234|srv=4.3.2.1/80|subj=cli|link=Ethernet or modem|raw_mtu=1500");
+
+        assert_eq!(
+            entry.event,
+            Event::Mtu {
+                link: Some("Ethernet or modem".to_string()),
+                raw_mtu: 1500,
+            }
+        );
+    }
+
+    #[test]
+    fn uptime() {
+        let entry = parse("[2024/01/01 12:00:00] mod=uptime|cli=1.2.3.4/1234|srv=4.3.2.1/80|subj=srv|uptime=11 days 4 hrs 16 min (modulo 198 days)|raw_freq=250.00 Hz");
+
+        assert_eq!(
+            entry.event,
+            Event::Uptime {
+                uptime: Duration::from_secs(((11 * 24 + 4) * 60 + 16) * 60),
+                modulo_days: 198,
+                raw_freq: 250.0,
+            }
+        );
+    }
+
+    #[test]
+    fn http_request() {
+        let entry = parse("[2024/01/01 12:00:00] mod=http request|cli=1.2.3.4/1234|srv=4.3.2.1/80|subj=cli|app=Firefox 10.x or newer|lang=English|params=dishonest|raw_sig=1:Host,User-Agent,Accept=[text/html,*/*;q=0.8],?Referer:Accept-Charset,Keep-Alive:Mozilla/5.0 (X11; Linux x86_64)");
+
+        let Event::HttpRequest(event) = entry.event else {
+            panic!("{:?}", entry.event);
+        };
+        assert_eq!(event.app.as_deref(), Some("Firefox 10.x or newer"));
+        assert_eq!(event.lang.as_deref(), Some("English"));
+        assert_eq!(event.params, ["dishonest"]);
+        assert_eq!(event.raw_sig.expsw, "Mozilla/5.0 (X11; Linux x86_64)");
+    }
+
+    #[test]
+    fn http_response() {
+        let entry = parse("[2024/01/01 12:00:00] mod=http response|cli=1.2.3.4/1234|srv=4.3.2.1/80|subj=srv|app=???|lang=none|params=none|raw_sig=1:Server,Date,Content-Type:Keep-Alive:nginx");
+
+        let Event::HttpResponse(event) = entry.event else {
+            panic!("{:?}", entry.event);
+        };
+        assert_eq!(event.app, None);
+        assert_eq!(event.lang, None);
+        assert!(event.params.is_empty());
+        assert_eq!(event.raw_sig.expsw, "nginx");
+    }
+
+    #[test]
+    fn host_change() {
+        let entry = parse("[2024/01/01 12:00:00] mod=host change|cli=1.2.3.4/1234|srv=4.3.2.1/80|subj=cli|reason=tstamp port|raw_hits=0,1,1,1");
+
+        assert_eq!(
+            entry.event,
+            Event::HostChange(ChangeEvent {
+                reason: vec!["tstamp".to_string(), "port".to_string()],
+                raw_hits: vec![0, 1, 1, 1],
+            })
+        );
+    }
+
+    #[test]
+    fn ip_sharing() {
+        let entry = parse("[2024/01/01 12:00:00] mod=ip sharing|cli=1.2.3.4/1234|srv=4.3.2.1/80|subj=cli|reason=ttl|raw_hits=1,0,0,0");
+
+        assert_eq!(
+            entry.event,
+            Event::IpSharing(ChangeEvent {
+                reason: vec!["ttl".to_string()],
+                raw_hits: vec![1, 0, 0, 0],
+            })
+        );
+    }
+
+    #[test]
+    fn malformed() {
+        const ADDRESSES: &str = "cli=1.2.3.4/1234|srv=4.3.2.1/80|subj=cli";
+
+        for (line, reason) in [
+            (format!("mod=mtu|{ADDRESSES}|link=???|raw_mtu=1500"), "missing time"),
+            (format!("[2024/01/01] mod=mtu|{ADDRESSES}"), "invalid time"),
+            (format!("[2024/01/01 12:00:00] mod=dns|{ADDRESSES}"), "unknown mod"),
+            (format!("[2024/01/01 12:00:00] {ADDRESSES}|link=???|raw_mtu=1500"), "missing mod"),
+            (format!("[2024/01/01 12:00:00] mod=mtu|{ADDRESSES}|link"), "expected key=value"),
+            (format!("[2024/01/01 12:00:00] mod=mtu|{ADDRESSES}|raw_mtu=1500"), "missing field"),
+            (format!("[2024/01/01 12:00:00] mod=mtu|{ADDRESSES}|link=???|raw_mtu=big"), "invalid raw_mtu"),
+            ("[2024/01/01 12:00:00] mod=mtu|cli=1.2.3.4|srv=4.3.2.1/80|subj=cli|link=???|raw_mtu=1500".to_string(), "address must be ip/port"),
+            ("[2024/01/01 12:00:00] mod=mtu|cli=1.2.3.4/1234|srv=4.3.2.1/80|subj=both|link=???|raw_mtu=1500".to_string(), "subj must be cli or srv"),
+            ("[2024/01/01 12:00:00] mod=mtu|cli=1.2.3.4/1234|subj=cli|link=???|raw_mtu=1500".to_string(), "missing srv"),
+            (format!("[2024/01/01 12:00:00] mod=uptime|{ADDRESSES}|uptime=1 days|raw_freq=100 Hz"), "uptime is missing its modulo"),
+            (format!("[2024/01/01 12:00:00] mod=uptime|{ADDRESSES}|uptime=1 weeks (modulo 49 days)|raw_freq=100 Hz"), "invalid uptime"),
+            (format!("[2024/01/01 12:00:00] mod=uptime|{ADDRESSES}|uptime=18446744073709551615 days 0 hrs 0 min (modulo 49 days)|raw_freq=100 Hz"), "invalid uptime"),
+            (format!("[2024/01/01 12:00:00] mod=uptime|{ADDRESSES}|uptime=307445734561825861 min (modulo 49 days)|raw_freq=100 Hz"), "invalid uptime"),
+            (format!("[2024/01/01 12:00:00] mod=host change|{ADDRESSES}|reason=ttl|raw_hits=1,x"), "invalid raw_hits"),
+            (format!("[2024/01/01 12:00:00] mod=syn|{ADDRESSES}|os=???|dist=0|params=none|raw_sig=4:64"), "tcp signature must have 8 fields"),
+        ] {
+            assert_eq!(line.parse::<LogEntry>(), Err(reason), "{line}");
+        }
+    }
+
+    #[test]
+    fn reader() {
+        let log = "\n[2024/01/01 12:00:00] mod=mtu|cli=1.2.3.4/1|srv=4.3.2.1/80|subj=cli|link=???|raw_mtu=1500\n\n[2024/01/01 12:00:00] mod=mtu\n[2024/01/01 12:00:00] mod=mtu|cli=1.2.3.4/2|srv=4.3.2.1/80|subj=cli|link=???|raw_mtu=1500\n";
+        let mut reader = LogReader::new(log.as_bytes());
+
+        assert_eq!(reader.next().unwrap().unwrap().client.port(), 1);
+        assert!(matches!(
+            reader.next().unwrap(),
+            Err(LogError::Malformed { line: 4, .. })
+        ));
+        assert_eq!(reader.next().unwrap().unwrap().client.port(), 2);
+        assert!(reader.next().is_none());
+    }
+}
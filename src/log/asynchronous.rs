@@ -0,0 +1,186 @@
+use std::{
+    future::{self, Future as _},
+    io,
+    path::Path,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use futures_core::Stream;
+use tokio::{
+    task::{self, JoinError, JoinHandle},
+    time::Sleep,
+};
+
+use super::{LogEntry, LogError, LogTailer, TailConfig};
+
+type Read = (LogTailer, Result<Option<LogEntry>, LogError>);
+
+/// [`LogTailer`] for tokio. Reading the log, and saving the checkpoint once
+/// caught up with it, happen on the blocking thread pool, and waiting for the
+/// log to grow doesn't block at all.
+///
+/// As a [`Stream`] it never ends, just like [`LogTailer`] as an iterator.
+/// Both the stream and [`AsyncLogTailer::next_entry`] are cancel safe, so
+/// they can be used with `tokio::select!`: a read cut short is picked up
+/// again by the next call, and no entry is lost.
+pub struct AsyncLogTailer {
+    tailer: Option<LogTailer>,
+    reading: Option<JoinHandle<Read>>,
+    waiting: Option<Pin<Box<Sleep>>>,
+    poll_interval: Duration,
+}
+
+impl AsyncLogTailer {
+    pub async fn open<T: AsRef<Path>>(path: T) -> io::Result<Self> {
+        Self::with_config(path, TailConfig::default()).await
+    }
+
+    /// See [`LogTailer::with_config`].
+    pub async fn with_config<T: AsRef<Path>>(path: T, config: TailConfig) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let tailer =
+            joined(task::spawn_blocking(move || LogTailer::with_config(path, config)).await)??;
+
+        Ok(tailer.into())
+    }
+
+    /// Waits for the next entry in the log.
+    pub async fn next_entry(&mut self) -> Result<LogEntry, LogError> {
+        future::poll_fn(|cx| self.poll_next_entry(cx)).await
+    }
+
+    fn poll_next_entry(&mut self, cx: &mut Context<'_>) -> Poll<Result<LogEntry, LogError>> {
+        loop {
+            if let Some(waiting) = &mut self.waiting {
+                ready!(waiting.as_mut().poll(cx));
+                self.waiting = None;
+            }
+
+            let reading = match &mut self.reading {
+                Some(reading) => reading,
+                None => {
+                    let mut tailer = self.tailer.take().ok_or_else(|| {
+                        io::Error::other("log tailer lost with the runtime it ran on")
+                    })?;
+                    self.reading.insert(task::spawn_blocking(move || {
+                        let result = match tailer.try_next() {
+                            Ok(None) => tailer.save_checkpoint().map(|()| None).map_err(Into::into),
+                            result => result,
+                        };
+                        (tailer, result)
+                    }))
+                }
+            };
+
+            let (tailer, result) = joined(ready!(Pin::new(reading).poll(cx)))?;
+            self.reading = None;
+            self.tailer = Some(tailer);
+
+            match result {
+                Ok(Some(entry)) => return Poll::Ready(Ok(entry)),
+                Ok(None) => {
+                    self.waiting = Some(Box::pin(tokio::time::sleep(self.poll_interval)));
+                }
+                Err(err) => return Poll::Ready(Err(err)),
+            }
+        }
+    }
+}
+
+impl Stream for AsyncLogTailer {
+    type Item = Result<LogEntry, LogError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_next_entry(cx).map(Some)
+    }
+}
+
+impl From<LogTailer> for AsyncLogTailer {
+    fn from(tailer: LogTailer) -> Self {
+        AsyncLogTailer {
+            poll_interval: tailer.config().poll_interval,
+            tailer: Some(tailer),
+            reading: None,
+            waiting: None,
+        }
+    }
+}
+
+/// Passes on a panic of a blocking task; the only other way for it to fail
+/// is the runtime shutting down.
+fn joined<T>(result: Result<T, JoinError>) -> io::Result<T> {
+    result.map_err(|err| match err.try_into_panic() {
+        Ok(panic) => std::panic::resume_unwind(panic),
+        Err(err) => io::Error::other(err),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+
+    const LINE: &str =
+        "[2024/01/01 12:00:00] mod=mtu|cli=1.2.3.4/1|srv=4.3.2.1/80|subj=cli|link=???|raw_mtu=1500";
+
+    fn log(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("p0f-rs-{}-{name}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn config() -> TailConfig {
+        TailConfig {
+            poll_interval: Duration::from_millis(10),
+            from_start: true,
+            ..Default::default()
+        }
+    }
+
+    async fn next(tailer: &mut AsyncLogTailer) -> Option<Result<LogEntry, LogError>> {
+        future::poll_fn(|cx| Pin::new(&mut *tailer).poll_next(cx)).await
+    }
+
+    #[tokio::test]
+    async fn stream() {
+        let path = log("stream");
+        fs::write(&path, format!("{LINE}\n{}\n", LINE.replace("/1|", "/2|"))).unwrap();
+        let mut tailer = AsyncLogTailer::with_config(&path, config()).await.unwrap();
+
+        assert_eq!(next(&mut tailer).await.unwrap().unwrap().client.port(), 1);
+        assert_eq!(next(&mut tailer).await.unwrap().unwrap().client.port(), 2);
+
+        // Caught up, so the stream waits instead of ending.
+        let waiting = tokio::time::timeout(Duration::from_millis(50), next(&mut tailer)).await;
+        assert!(waiting.is_err());
+
+        fs::write(
+            &path,
+            format!("{LINE}\n{LINE}\n{}\n", LINE.replace("/1|", "/3|")),
+        )
+        .unwrap();
+        assert_eq!(next(&mut tailer).await.unwrap().unwrap().client.port(), 3);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn cancelled() {
+        let path = log("cancelled");
+        let mut tailer = AsyncLogTailer::with_config(&path, config()).await.unwrap();
+
+        for _ in 0..3 {
+            let waiting =
+                tokio::time::timeout(Duration::from_millis(15), tailer.next_entry()).await;
+            assert!(waiting.is_err());
+        }
+
+        fs::write(&path, format!("{LINE}\n")).unwrap();
+        assert_eq!(tailer.next_entry().await.unwrap().client.port(), 1);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,310 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufRead as _, BufReader, Seek as _, SeekFrom},
+    os::unix::fs::MetadataExt as _,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use super::{LogEntry, LogError};
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Debug)]
+pub struct TailConfig {
+    /// How long to wait for the log to grow before looking again.
+    pub poll_interval: Duration,
+    /// File to keep the read offset in, so that a new tailer picks up where
+    /// the last one stopped.
+    pub checkpoint: Option<PathBuf>,
+    /// Start at the beginning of the log rather than its end when there is
+    /// no checkpoint for it.
+    pub from_start: bool,
+}
+
+impl Default for TailConfig {
+    fn default() -> Self {
+        TailConfig {
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            checkpoint: None,
+            from_start: false,
+        }
+    }
+}
+
+/// Follows a log p0f is writing to, like `tail -F`.
+///
+/// When the log is rotated, the tailer finishes the old file and moves on to
+/// the new one from its start; when it is truncated, it starts over from the
+/// beginning. The iterator never ends, it waits for the log to grow instead.
+/// With the `tokio` feature, [`AsyncLogTailer`](super::AsyncLogTailer) does
+/// the same without blocking the runtime.
+///
+/// The checkpoint is saved whenever the tailer has caught up with the log,
+/// which is only after every entry before it has been asked for. An entry is
+/// therefore seen again after a restart rather than lost, unless
+/// [`LogTailer::save_checkpoint`] is called early. Line numbers in errors
+/// count from where the tailer started reading the current file.
+///
+/// A line p0f never finished before the log was rotated or truncated is
+/// reported as [`LogError::Malformed`], and reading goes on after it.
+pub struct LogTailer {
+    path: PathBuf,
+    config: TailConfig,
+    file: Option<OpenFile>,
+    pending: Vec<u8>,
+    saved: Option<Checkpoint>,
+}
+
+struct OpenFile {
+    reader: BufReader<File>,
+    id: FileId,
+    offset: u64,
+    line: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct FileId {
+    dev: u64,
+    ino: u64,
+}
+
+impl FileId {
+    fn of(metadata: &fs::Metadata) -> Self {
+        FileId {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+        }
+    }
+}
+
+/// `<dev> <ino> <offset>`, enough to tell whether the log was rotated in
+/// the meantime.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Checkpoint {
+    id: FileId,
+    offset: u64,
+}
+
+impl LogTailer {
+    pub fn open<T: AsRef<Path>>(path: T) -> io::Result<Self> {
+        Self::with_config(path, TailConfig::default())
+    }
+
+    /// The log doesn't need to exist yet; once it does, it is read from its
+    /// start.
+    pub fn with_config<T: AsRef<Path>>(path: T, config: TailConfig) -> io::Result<Self> {
+        let saved = match &config.checkpoint {
+            Some(checkpoint) => read_checkpoint(checkpoint)?,
+            None => None,
+        };
+
+        let mut tailer = LogTailer {
+            path: path.as_ref().to_path_buf(),
+            config,
+            file: None,
+            pending: Vec::new(),
+            saved,
+        };
+
+        if let Some((file, id, len)) = tailer.open_log()? {
+            let offset = match saved {
+                Some(saved) if saved.id == id && saved.offset <= len => saved.offset,
+                // Rotated or truncated since the checkpoint was saved.
+                Some(_) => 0,
+                None if tailer.config.from_start => 0,
+                None => len,
+            };
+            tailer.file = Some(OpenFile::at(file, id, offset)?);
+        }
+
+        Ok(tailer)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn config(&self) -> &TailConfig {
+        &self.config
+    }
+
+    /// Offset in the current file up to which entries have been returned.
+    pub fn offset(&self) -> Option<u64> {
+        self.file.as_ref().map(|file| file.offset)
+    }
+
+    /// Returns the next entry if there is one in the log already, or `None`
+    /// once caught up with it.
+    pub fn try_next(&mut self) -> Result<Option<LogEntry>, LogError> {
+        loop {
+            let Some(file) = &mut self.file else {
+                match self.open_log()? {
+                    Some((file, id, _)) => self.file = Some(OpenFile::at(file, id, 0)?),
+                    None => return Ok(None),
+                }
+                continue;
+            };
+
+            let read = file.reader.read_until(b'\n', &mut self.pending)?;
+            if self.pending.ends_with(b"\n") {
+                file.offset += self.pending.len() as u64;
+                file.line += 1;
+                let line = String::from_utf8_lossy(&self.pending).into_owned();
+                self.pending.clear();
+
+                let line = line.trim_end();
+                if line.is_empty() {
+                    continue;
+                }
+                return line
+                    .parse()
+                    .map(Some)
+                    .map_err(|reason| LogError::Malformed {
+                        line: file.line,
+                        reason,
+                    });
+            }
+
+            // Whatever is pending is a line p0f is still writing.
+            if read == 0 && !self.follow()? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Records the current offset in the checkpoint file, if there is one.
+    pub fn save_checkpoint(&mut self) -> io::Result<()> {
+        let (Some(path), Some(file)) = (&self.config.checkpoint, &self.file) else {
+            return Ok(());
+        };
+        let checkpoint = Checkpoint {
+            id: file.id,
+            offset: file.offset,
+        };
+        if self.saved == Some(checkpoint) {
+            return Ok(());
+        }
+
+        // Written next to it and renamed over it, so that a crash never
+        // leaves half a checkpoint behind.
+        let mut temporary = path.clone().into_os_string();
+        temporary.push(".tmp");
+        fs::write(
+            &temporary,
+            format!(
+                "{} {} {}\n",
+                checkpoint.id.dev, checkpoint.id.ino, checkpoint.offset
+            ),
+        )?;
+        fs::rename(&temporary, path)?;
+
+        self.saved = Some(checkpoint);
+        Ok(())
+    }
+
+    fn open_log(&self) -> io::Result<Option<(File, FileId, u64)>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let metadata = file.metadata()?;
+
+        Ok(Some((file, FileId::of(&metadata), metadata.len())))
+    }
+
+    /// Checks whether the log was rotated or truncated once the current file
+    /// has been read to its end, and returns whether there is more to read
+    /// because of it.
+    fn follow(&mut self) -> Result<bool, LogError> {
+        let Some(file) = &mut self.file else {
+            return Ok(false);
+        };
+        let metadata = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            // Moved away, with the new one not created yet.
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err.into()),
+        };
+        let line = file.line + 1;
+
+        let reason = if FileId::of(&metadata) != file.id {
+            // Whatever p0f wrote to the old file since it was last read,
+            // like the rest of a line, comes before the new file.
+            if file.reader.read_until(b'\n', &mut self.pending)? > 0 {
+                return Ok(true);
+            }
+            let Some((new, id, _)) = self.open_log()? else {
+                return Ok(false);
+            };
+            self.file = Some(OpenFile::at(new, id, 0)?);
+            "unterminated line at the end of a rotated log"
+        } else if metadata.len() < file.offset + self.pending.len() as u64 {
+            file.reader.seek(SeekFrom::Start(0))?;
+            file.offset = 0;
+            file.line = 0;
+            "unterminated line in a truncated log"
+        } else {
+            return Ok(false);
+        };
+
+        if self.pending.is_empty() {
+            return Ok(true);
+        }
+        self.pending.clear();
+        Err(LogError::Malformed { line, reason })
+    }
+}
+
+impl OpenFile {
+    fn at(mut file: File, id: FileId, offset: u64) -> io::Result<Self> {
+        file.seek(SeekFrom::Start(offset))?;
+
+        Ok(OpenFile {
+            reader: BufReader::new(file),
+            id,
+            offset,
+            line: 0,
+        })
+    }
+}
+
+impl Iterator for LogTailer {
+    type Item = Result<LogEntry, LogError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.try_next() {
+                Ok(Some(entry)) => return Some(Ok(entry)),
+                Ok(None) => {}
+                Err(err) => return Some(Err(err)),
+            }
+            if let Err(err) = self.save_checkpoint() {
+                return Some(Err(err.into()));
+            }
+            thread::sleep(self.config.poll_interval);
+        }
+    }
+}
+
+fn read_checkpoint(path: &Path) -> io::Result<Option<Checkpoint>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed log checkpoint");
+    let mut numbers = text.split_whitespace().map(str::parse::<u64>);
+    let mut next = || numbers.next().and_then(Result::ok).ok_or_else(invalid);
+
+    Ok(Some(Checkpoint {
+        id: FileId {
+            dev: next()?,
+            ino: next()?,
+        },
+        offset: next()?,
+    }))
+}
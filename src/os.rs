@@ -0,0 +1,432 @@
+//! Operating systems as p0f names them, grouped into families that can be
+//! compared with what other sources, like a `User-Agent`, say, and with their
+//! flavors taken apart by [`OsInfo`].
+
+use std::fmt;
+
+use crate::Response;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OsFamily {
+    Windows,
+    Linux,
+    Android,
+    ChromeOs,
+    MacOs,
+    Ios,
+    FreeBsd,
+    OpenBsd,
+    NetBsd,
+    Solaris,
+    HpUx,
+    OpenVms,
+    Tru64,
+    NextStep,
+    BlackBerry,
+    Nintendo,
+}
+
+impl OsFamily {
+    /// The family of a p0f OS name, as in `os_name`, with its flavor. p0f
+    /// files Android under `Linux` and iPhones under `Mac OS X`, telling
+    /// them apart by the flavor only.
+    pub fn from_p0f(name: &str, flavor: Option<&str>) -> Option<OsFamily> {
+        let flavor = flavor.unwrap_or_default();
+
+        Some(match name {
+            "Windows" => OsFamily::Windows,
+            "Linux" if flavor.starts_with("Android") => OsFamily::Android,
+            "Linux" => OsFamily::Linux,
+            "Android" => OsFamily::Android,
+            "Mac OS X" if flavor.starts_with("iPhone") => OsFamily::Ios,
+            "Mac OS X" => OsFamily::MacOs,
+            "iOS" => OsFamily::Ios,
+            "FreeBSD" => OsFamily::FreeBsd,
+            "OpenBSD" => OsFamily::OpenBsd,
+            "NetBSD" => OsFamily::NetBsd,
+            "Solaris" => OsFamily::Solaris,
+            "HP-UX" => OsFamily::HpUx,
+            "OpenVMS" => OsFamily::OpenVms,
+            "Tru64" => OsFamily::Tru64,
+            "NeXTSTEP" => OsFamily::NextStep,
+            "Blackberry" => OsFamily::BlackBerry,
+            "Nintendo" => OsFamily::Nintendo,
+            _ => return None,
+        })
+    }
+
+    /// The family whose TCP/IP stack this one uses, which is all a TCP
+    /// fingerprint can tell apart.
+    pub fn kernel(self) -> OsFamily {
+        match self {
+            OsFamily::Android | OsFamily::ChromeOs => OsFamily::Linux,
+            OsFamily::Ios => OsFamily::MacOs,
+            family => family,
+        }
+    }
+}
+
+impl fmt::Display for OsFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OsFamily::Windows => "Windows",
+            OsFamily::Linux => "Linux",
+            OsFamily::Android => "Android",
+            OsFamily::ChromeOs => "Chrome OS",
+            OsFamily::MacOs => "Mac OS X",
+            OsFamily::Ios => "iOS",
+            OsFamily::FreeBsd => "FreeBSD",
+            OsFamily::OpenBsd => "OpenBSD",
+            OsFamily::NetBsd => "NetBSD",
+            OsFamily::Solaris => "Solaris",
+            OsFamily::HpUx => "HP-UX",
+            OsFamily::OpenVms => "OpenVMS",
+            OsFamily::Tru64 => "Tru64",
+            OsFamily::NextStep => "NeXTSTEP",
+            OsFamily::BlackBerry => "Blackberry",
+            OsFamily::Nintendo => "Nintendo",
+        })
+    }
+}
+
+/// An `os_name` and `os_flavor` taken apart, e.g. `Linux` and
+/// `3.11 and newer` into [`OsFamily::Linux`] and `>= 3.11`. Whatever isn't
+/// understood is still in `name` and `flavor`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OsInfo {
+    /// `None` for names that aren't an OS, like `NMap`, or unknown to this
+    /// crate.
+    pub family: Option<OsFamily>,
+    /// The product version, e.g. `7 or 8` for Windows.
+    pub version: Option<VersionRange>,
+    /// The kernel version of Windows flavors that give that instead, like
+    /// `NT kernel 6.x`. Kept apart from `version`, since Windows 7 is NT 6.1.
+    pub nt_kernel: Option<VersionRange>,
+    /// The rest of the flavor, e.g. `loopback` for `2.6.x (loopback)` or
+    /// `NT kernel` for `NT kernel 6.x`.
+    pub detail: Option<String>,
+    pub name: String,
+    pub flavor: Option<String>,
+}
+
+/// A version like `3.11`, or `2.6.x` for any `2.6` release.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Version {
+    pub major: u32,
+    pub minor: Option<u32>,
+    /// Ends in `.x`.
+    pub any: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum VersionRange {
+    /// `2.6.x`
+    Exact(Version),
+    /// `3.1-3.10`
+    Between(Version, Version),
+    /// `3.11 and newer`
+    AtLeast(Version),
+    /// `4.6 or earlier`
+    AtMost(Version),
+    /// `7, 8 or 8.1`
+    OneOf(Vec<Version>),
+}
+
+impl OsInfo {
+    pub fn new(name: &str, flavor: Option<&str>) -> Self {
+        let (mut version, detail) = match flavor {
+            Some(flavor) => parse_flavor(flavor),
+            None => (None, None),
+        };
+        let family = OsFamily::from_p0f(name, flavor);
+        let nt_kernel = match (family, detail.as_deref()) {
+            (Some(OsFamily::Windows), Some(detail)) if detail.starts_with("NT kernel") => {
+                version.take()
+            }
+            _ => None,
+        };
+
+        OsInfo {
+            family,
+            version,
+            nt_kernel,
+            detail,
+            name: name.to_string(),
+            flavor: flavor.map(str::to_string),
+        }
+    }
+
+    /// `None` if p0f has no OS for the host.
+    pub fn from_response(response: &Response) -> Option<Self> {
+        let name = response.os_name.as_deref()?;
+        Some(OsInfo::new(name, response.os_flavor.as_deref()))
+    }
+
+    /// Phones, tablets and handheld consoles.
+    pub fn is_mobile(&self) -> bool {
+        match self.family {
+            Some(OsFamily::Android | OsFamily::Ios | OsFamily::BlackBerry) => true,
+            Some(OsFamily::Nintendo) => self.detail.as_deref() == Some("3DS"),
+            _ => false,
+        }
+    }
+
+    /// Systems mostly found running servers: the BSDs, commercial Unixes,
+    /// OpenVMS, and Linux other than on phones and laptops.
+    pub fn is_server_class(&self) -> bool {
+        matches!(
+            self.family,
+            Some(
+                OsFamily::Linux
+                    | OsFamily::FreeBsd
+                    | OsFamily::OpenBsd
+                    | OsFamily::NetBsd
+                    | OsFamily::Solaris
+                    | OsFamily::HpUx
+                    | OsFamily::OpenVms
+                    | OsFamily::Tru64
+            )
+        )
+    }
+
+    /// Web crawlers p0f recognizes by their TCP/IP stack, like
+    /// `BaiduSpider` or `Linux 2.6.x (Google crawler)`.
+    pub fn is_crawler(&self) -> bool {
+        self.name == "BaiduSpider"
+            || self
+                .detail
+                .as_deref()
+                .is_some_and(|detail| detail.ends_with("crawler"))
+    }
+
+    /// Packets crafted by a userland tool rather than an OS, like `NMap`
+    /// scans.
+    pub fn is_tool(&self) -> bool {
+        matches!(self.name.as_str(), "NMap" | "p0f")
+    }
+}
+
+impl fmt::Display for OsInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)?;
+        match &self.flavor {
+            Some(flavor) => write!(f, " {flavor}"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Version {
+    /// `3.11`, `2.6.x`, `3.x` or `10`.
+    pub fn parse(version: &str) -> Option<Version> {
+        let (version, any) = match version.strip_suffix(".x") {
+            Some(version) => (version, true),
+            None => (version, false),
+        };
+        let (major, minor) = match version.split_once('.') {
+            Some((major, minor)) => (major, Some(minor)),
+            None => (version, None),
+        };
+        let number = |part: &str| {
+            (!part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()))
+                .then(|| part.parse().ok())
+                .flatten()
+        };
+
+        Some(Version {
+            major: number(major)?,
+            minor: match minor {
+                Some(minor) => Some(number(minor)?),
+                None => None,
+            },
+            any,
+        })
+    }
+
+    fn key(self) -> (u32, u32) {
+        (self.major, self.minor.unwrap_or(0))
+    }
+
+    /// Whether `other` is this version, or one of the releases it stands
+    /// for.
+    pub fn matches(self, other: Version) -> bool {
+        self.major == other.major && (self.minor.is_none() || self.minor == other.minor)
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.major)?;
+        if let Some(minor) = self.minor {
+            write!(f, ".{minor}")?;
+        }
+        if self.any {
+            f.write_str(".x")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl VersionRange {
+    /// `2.6.x`, `3.1-3.10`, `3.11 and newer`, `10.9 or newer`,
+    /// `4.6 or earlier`, `7 or 8` or `7, 8 or 8.1`.
+    pub fn parse(range: &str) -> Option<VersionRange> {
+        if let Some(version) = range
+            .strip_suffix(" and newer")
+            .or_else(|| range.strip_suffix(" or newer"))
+        {
+            return Some(VersionRange::AtLeast(Version::parse(version)?));
+        }
+        if let Some(version) = range.strip_suffix(" or earlier") {
+            return Some(VersionRange::AtMost(Version::parse(version)?));
+        }
+        if let Some((first, last)) = range.split_once('-') {
+            return Some(VersionRange::Between(
+                Version::parse(first)?,
+                Version::parse(last)?,
+            ));
+        }
+        if let Some((rest, last)) = range.rsplit_once(" or ") {
+            return rest
+                .split(", ")
+                .chain([last])
+                .map(Version::parse)
+                .collect::<Option<_>>()
+                .map(VersionRange::OneOf);
+        }
+
+        Version::parse(range).map(VersionRange::Exact)
+    }
+
+    pub fn contains(&self, version: Version) -> bool {
+        match self {
+            VersionRange::Exact(exact) => exact.matches(version),
+            VersionRange::Between(first, last) => {
+                version.key() >= first.key()
+                    && (last.matches(version) || version.key() <= last.key())
+            }
+            VersionRange::AtLeast(first) => version.key() >= first.key(),
+            VersionRange::AtMost(last) => last.matches(version) || version.key() <= last.key(),
+            VersionRange::OneOf(versions) => versions.iter().any(|one| one.matches(version)),
+        }
+    }
+}
+
+impl fmt::Display for VersionRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VersionRange::Exact(version) => write!(f, "{version}"),
+            VersionRange::Between(first, last) => write!(f, "{first}-{last}"),
+            VersionRange::AtLeast(first) => write!(f, ">= {first}"),
+            VersionRange::AtMost(last) => write!(f, "<= {last}"),
+            VersionRange::OneOf(versions) => {
+                for (i, version) in versions.iter().enumerate() {
+                    match i {
+                        0 => {}
+                        _ if i == versions.len() - 1 => f.write_str(" or ")?,
+                        _ => f.write_str(", ")?,
+                    }
+                    write!(f, "{version}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Splits a flavor into its version range and whatever else it says, like
+/// `2.6.x (loopback)` or `NT kernel 6.x`.
+fn parse_flavor(flavor: &str) -> (Option<VersionRange>, Option<String>) {
+    let (text, remark) = match flavor.split_once(" (") {
+        Some((text, remark)) => (text, remark.strip_suffix(')')),
+        None => (flavor, None),
+    };
+
+    // The version comes last, after any words that describe it.
+    let (words, version) = match VersionRange::parse(text) {
+        Some(version) => ("", Some(version)),
+        None => match text.split_once(|c: char| c.is_ascii_digit()) {
+            Some((words, _)) if words.ends_with(' ') => {
+                match VersionRange::parse(&text[words.len()..]) {
+                    Some(version) => (words.trim_end(), Some(version)),
+                    None => (text, None),
+                }
+            }
+            _ => (text, None),
+        },
+    };
+
+    let detail = [words, remark.unwrap_or_default()]
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    (version, (!detail.is_empty()).then_some(detail))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fpdb::Database;
+
+    const STOCK: &str = include_str!("../tests/data/p0f.fp");
+
+    fn version(version: &str) -> Version {
+        Version::parse(version).unwrap()
+    }
+
+    #[test]
+    fn stock_labels() {
+        let db = Database::parse(STOCK).unwrap();
+
+        for label in db.labels() {
+            let flavor = label.flavor.as_deref();
+            let info = OsInfo::new(&label.name, flavor);
+            assert_eq!(info.name, label.name);
+
+            // Every flavor that starts with a version has one, save for one
+            // that names two browsers and the 3DS.
+            let unversioned = ["10.x or Safari 5.x", "3DS"];
+            if let Some(flavor) = flavor.filter(|flavor| !unversioned.contains(flavor)) {
+                if flavor.starts_with(|c: char| c.is_ascii_digit()) {
+                    assert!(info.version.is_some(), "{label}");
+                }
+            }
+            // Every OS p0f knows has a family.
+            if label.class.is_some() && !info.is_tool() && label.name != "BaiduSpider" {
+                assert!(info.family.is_some(), "{label}");
+            }
+            if info.family == Some(OsFamily::Windows) {
+                assert!(
+                    info.version.is_none() || info.nt_kernel.is_none(),
+                    "{label}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn windows_versions() {
+        let kernel = OsInfo::new("Windows", Some("NT kernel 6.x"));
+        assert_eq!(kernel.version, None);
+        assert_eq!(kernel.detail.as_deref(), Some("NT kernel"));
+        let nt_kernel = kernel.nt_kernel.unwrap();
+        assert!(nt_kernel.contains(version("6.1")));
+        assert!(!nt_kernel.contains(version("7")));
+
+        let product = OsInfo::new("Windows", Some("7, 8 or 8.1"));
+        assert_eq!(product.nt_kernel, None);
+        let version_range = product.version.unwrap();
+        assert!(version_range.contains(version("8.1")));
+        assert!(!version_range.contains(version("6.1")));
+
+        let kernel = OsInfo::new("Windows", Some("NT kernel"));
+        assert_eq!((kernel.version, kernel.nt_kernel), (None, None));
+        assert_eq!(
+            OsInfo::new("Windows", Some("XP")).detail.as_deref(),
+            Some("XP")
+        );
+    }
+}
@@ -0,0 +1,449 @@
+//! Decoding of IPv4/IPv6 + TCP packets into what p0f looks at when it
+//! fingerprints a SYN or SYN+ACK.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::signature::{IpVersion, PayloadClass, Quirk, TcpOption, TcpSignature, Ttl, WindowSize};
+
+const IPV4_HEADER_SIZE: usize = 20;
+const IPV6_HEADER_SIZE: usize = 40;
+const TCP_HEADER_SIZE: usize = 20;
+const PROTO_TCP: u8 = 6;
+const ETHERNET_MTU: u16 = 1500;
+// Systems that use timestamps sometimes take their length off the MSS.
+const TS_OPTION_SIZE: u16 = 12;
+
+const IP4_MBZ: u16 = 0x8000;
+const IP4_DF: u16 = 0x4000;
+const IP4_MF: u16 = 0x2000;
+const IP4_OFFSET: u16 = 0x1fff;
+const IP_TOS_ECN: u8 = 0x03;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_PUSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+const TCP_URG: u8 = 0x20;
+const TCP_ECE: u8 = 0x40;
+const TCP_CWR: u8 = 0x80;
+const TCP_NS: u8 = 0x01;
+
+const OPT_EOL: u8 = 0;
+const OPT_NOP: u8 = 1;
+const OPT_MSS: u8 = 2;
+const OPT_WS: u8 = 3;
+const OPT_SOK: u8 = 4;
+const OPT_SACK: u8 = 5;
+const OPT_TS: u8 = 8;
+
+// Window scale factors above this are not allowed by RFC 7323.
+const MAX_WSCALE: u8 = 14;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PacketError {
+    #[error("truncated {0}")]
+    Truncated(&'static str),
+    #[error("malformed {0}")]
+    Malformed(&'static str),
+    #[error("unsupported {0}")]
+    Unsupported(&'static str),
+}
+
+/// A TCP segment, decoded as far as p0f needs it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TcpPacket {
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    pub ttl: u8,
+    /// Length of the IP options, in bytes.
+    pub ip_olen: u8,
+    /// Length of the TCP options, in bytes.
+    pub tcp_olen: u8,
+    pub flags: u8,
+    pub window: u16,
+    pub mss: Option<u16>,
+    pub wscale: Option<u8>,
+    /// The sender's and the echoed peer timestamp, if the option is present.
+    pub timestamps: Option<(u32, u32)>,
+    pub olayout: Vec<TcpOption>,
+    /// In the order p0f prints them.
+    pub quirks: Vec<Quirk>,
+    pub payload_len: usize,
+}
+
+impl TcpPacket {
+    /// Decodes a packet that starts with its IP header.
+    pub fn parse(packet: &[u8]) -> Result<Self, PacketError> {
+        let version = packet.first().ok_or(PacketError::Truncated("ip header"))? >> 4;
+        let mut quirks = Vec::new();
+
+        let (source, destination, ttl, ip_olen, segment) = match version {
+            4 => {
+                let header = packet
+                    .get(..IPV4_HEADER_SIZE)
+                    .ok_or(PacketError::Truncated("ip header"))?;
+                let header_len = usize::from(header[0] & 0x0f) * 4;
+                let total_len = usize::from(u16::from_be_bytes([header[2], header[3]]));
+                if header_len < IPV4_HEADER_SIZE || total_len < header_len {
+                    return Err(PacketError::Malformed("ip header"));
+                }
+                let packet = packet
+                    .get(..total_len)
+                    .ok_or(PacketError::Truncated("ip packet"))?;
+                if packet.len() < header_len {
+                    return Err(PacketError::Truncated("ip options"));
+                }
+                if header[9] != PROTO_TCP {
+                    return Err(PacketError::Unsupported("ip protocol"));
+                }
+
+                let id = u16::from_be_bytes([header[4], header[5]]);
+                let flags = u16::from_be_bytes([header[6], header[7]]);
+                if flags & (IP4_MF | IP4_OFFSET) != 0 {
+                    return Err(PacketError::Unsupported("ip fragment"));
+                }
+
+                if flags & IP4_DF != 0 {
+                    quirks.push(Quirk::Df);
+                    if id != 0 {
+                        quirks.push(Quirk::NonZeroId);
+                    }
+                } else if id == 0 {
+                    quirks.push(Quirk::ZeroId);
+                }
+                if header[1] & IP_TOS_ECN != 0 {
+                    quirks.push(Quirk::Ecn);
+                }
+                if flags & IP4_MBZ != 0 {
+                    quirks.push(Quirk::MustBeZero);
+                }
+
+                let source = Ipv4Addr::new(header[12], header[13], header[14], header[15]);
+                let destination = Ipv4Addr::new(header[16], header[17], header[18], header[19]);
+                (
+                    IpAddr::V4(source),
+                    IpAddr::V4(destination),
+                    header[8],
+                    (header_len - IPV4_HEADER_SIZE) as u8,
+                    &packet[header_len..],
+                )
+            }
+            6 => {
+                let header = packet
+                    .get(..IPV6_HEADER_SIZE)
+                    .ok_or(PacketError::Truncated("ip header"))?;
+                let payload_len = usize::from(u16::from_be_bytes([header[4], header[5]]));
+                let packet = packet
+                    .get(..IPV6_HEADER_SIZE + payload_len)
+                    .ok_or(PacketError::Truncated("ip packet"))?;
+                // Like p0f, don't bother walking extension headers.
+                if header[6] != PROTO_TCP {
+                    return Err(PacketError::Unsupported("ip protocol"));
+                }
+
+                let traffic_class = (header[0] << 4) | (header[1] >> 4);
+                if traffic_class & IP_TOS_ECN != 0 {
+                    quirks.push(Quirk::Ecn);
+                }
+                if u32::from_be_bytes([0, header[1] & 0x0f, header[2], header[3]]) != 0 {
+                    quirks.push(Quirk::FlowId);
+                }
+
+                let source = <[u8; 16]>::try_from(&header[8..24]).expect("16 bytes");
+                let destination = <[u8; 16]>::try_from(&header[24..40]).expect("16 bytes");
+                (
+                    IpAddr::V6(Ipv6Addr::from(source)),
+                    IpAddr::V6(Ipv6Addr::from(destination)),
+                    header[7],
+                    0,
+                    &packet[IPV6_HEADER_SIZE..],
+                )
+            }
+            _ => return Err(PacketError::Unsupported("ip version")),
+        };
+
+        let header = segment
+            .get(..TCP_HEADER_SIZE)
+            .ok_or(PacketError::Truncated("tcp header"))?;
+        let header_len = usize::from(header[12] >> 4) * 4;
+        if header_len < TCP_HEADER_SIZE {
+            return Err(PacketError::Malformed("tcp header"));
+        }
+        let options = segment
+            .get(TCP_HEADER_SIZE..header_len)
+            .ok_or(PacketError::Truncated("tcp options"))?;
+
+        let source_port = u16::from_be_bytes([header[0], header[1]]);
+        let destination_port = u16::from_be_bytes([header[2], header[3]]);
+        let seq = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+        let ack = u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+        let flags = header[13];
+        let window = u16::from_be_bytes([header[14], header[15]]);
+        let urgent = u16::from_be_bytes([header[18], header[19]]);
+
+        if flags & (TCP_ECE | TCP_CWR) != 0 || header[12] & TCP_NS != 0 {
+            push_quirk(&mut quirks, Quirk::Ecn);
+        }
+        if seq == 0 {
+            quirks.push(Quirk::SeqZero);
+        }
+        if flags & TCP_ACK != 0 {
+            if ack == 0 {
+                quirks.push(Quirk::AckZero);
+            }
+        } else if ack != 0 {
+            quirks.push(Quirk::AckNonZero);
+        }
+        if flags & TCP_URG != 0 {
+            quirks.push(Quirk::Urg);
+        } else if urgent != 0 {
+            quirks.push(Quirk::UptrNonZero);
+        }
+        if flags & TCP_PUSH != 0 {
+            quirks.push(Quirk::Push);
+        }
+
+        let mut packet = TcpPacket {
+            source: SocketAddr::new(source, source_port),
+            destination: SocketAddr::new(destination, destination_port),
+            ttl,
+            ip_olen,
+            tcp_olen: (header_len - TCP_HEADER_SIZE) as u8,
+            flags,
+            window,
+            mss: None,
+            wscale: None,
+            timestamps: None,
+            olayout: Vec::new(),
+            quirks,
+            payload_len: segment.len() - header_len,
+        };
+        packet.parse_options(options);
+        packet.sort_quirks();
+
+        Ok(packet)
+    }
+
+    fn parse_options(&mut self, mut options: &[u8]) {
+        while let Some((&kind, rest)) = options.split_first() {
+            options = rest;
+
+            match kind {
+                OPT_EOL => {
+                    self.olayout.push(TcpOption::Eol(options.len() as u8));
+                    if options.iter().any(|&byte| byte != 0) {
+                        self.quirks.push(Quirk::OptNonZero);
+                    }
+                    return;
+                }
+                OPT_NOP => {
+                    self.olayout.push(TcpOption::Nop);
+                    continue;
+                }
+                _ => {}
+            }
+
+            self.olayout.push(match kind {
+                OPT_MSS => TcpOption::Mss,
+                OPT_WS => TcpOption::Ws,
+                OPT_SOK => TcpOption::Sok,
+                OPT_SACK => TcpOption::Sack,
+                OPT_TS => TcpOption::Ts,
+                kind => TcpOption::Unknown(kind),
+            });
+
+            let Some(len) = options.first().map(|&len| usize::from(len)) else {
+                self.quirks.push(Quirk::OptBad);
+                return;
+            };
+            let valid = match kind {
+                OPT_MSS => len == 4,
+                OPT_WS => len == 3,
+                OPT_SOK => len == 2,
+                OPT_SACK => (10..=34).contains(&len),
+                OPT_TS => len == 10,
+                _ => len >= 2,
+            };
+            // The length covers the kind and length bytes too.
+            let Some(data) = options.get(1..len.max(2) - 1).filter(|_| valid) else {
+                self.quirks.push(Quirk::OptBad);
+                return;
+            };
+            options = &options[len - 1..];
+
+            match kind {
+                OPT_MSS => self.mss = Some(u16::from_be_bytes([data[0], data[1]])),
+                OPT_WS => {
+                    self.wscale = Some(data[0]);
+                    if data[0] > MAX_WSCALE {
+                        self.quirks.push(Quirk::ExcessiveWs);
+                    }
+                }
+                OPT_TS => {
+                    let ts1 = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+                    let ts2 = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+                    if ts1 == 0 {
+                        self.quirks.push(Quirk::Ts1Zero);
+                    }
+                    if ts2 != 0 && self.flags & TCP_ACK == 0 {
+                        self.quirks.push(Quirk::Ts2NonZero);
+                    }
+                    self.timestamps = Some((ts1, ts2));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn sort_quirks(&mut self) {
+        self.quirks.sort_by_key(|quirk| {
+            Quirk::ALL
+                .iter()
+                .position(|(known, _)| known == quirk)
+                .expect("every quirk is known")
+        });
+    }
+
+    pub fn version(&self) -> IpVersion {
+        match self.source {
+            SocketAddr::V4(_) => IpVersion::V4,
+            SocketAddr::V6(_) => IpVersion::V6,
+        }
+    }
+
+    /// A SYN without ACK, matched against `[tcp:request]`.
+    pub fn is_syn(&self) -> bool {
+        self.flags & (TCP_SYN | TCP_ACK | TCP_FIN | TCP_RST) == TCP_SYN
+    }
+
+    /// A SYN+ACK, matched against `[tcp:response]`.
+    pub fn is_syn_ack(&self) -> bool {
+        self.flags & (TCP_SYN | TCP_ACK | TCP_FIN | TCP_RST) == TCP_SYN | TCP_ACK
+    }
+
+    /// The link MTU implied by the MSS, which is how p0f picks the `[mtu]`
+    /// label.
+    pub fn mtu(&self) -> Option<u16> {
+        mtu(self.version(), self.mss?)
+    }
+
+    /// The hops from the sender, assuming it started out with the nearest
+    /// common initial TTL above the one seen.
+    pub fn guess_distance(&self) -> u8 {
+        guess_distance(self.ttl)
+    }
+
+    /// The window size as a multiple of the MSS or MTU if p0f takes it for
+    /// one, or else as is.
+    pub(crate) fn window_size(&self) -> WindowSize {
+        let ip_header = match self.version() {
+            IpVersion::V6 => IPV6_HEADER_SIZE,
+            _ => IPV4_HEADER_SIZE + usize::from(self.ip_olen),
+        };
+        let headers = ip_header + TCP_HEADER_SIZE + usize::from(self.tcp_olen);
+
+        window_size(
+            self.version(),
+            self.window,
+            self.mss.unwrap_or(0),
+            self.timestamps.is_some_and(|(ts1, _)| ts1 != 0),
+            headers as u16,
+        )
+    }
+
+    /// The signature as p0f prints it in its `raw_sig`.
+    pub fn signature(&self) -> TcpSignature {
+        TcpSignature {
+            version: self.version(),
+            ittl: Ttl::Distance(self.ttl, self.guess_distance()),
+            olen: self.ip_olen,
+            mss: Some(self.mss.unwrap_or(0)),
+            wsize: self.window_size(),
+            wscale: Some(self.wscale.unwrap_or(0)),
+            olayout: self.olayout.clone(),
+            quirks: self.quirks.clone(),
+            pclass: match self.payload_len {
+                0 => PayloadClass::Zero,
+                _ => PayloadClass::NonZero,
+            },
+        }
+    }
+}
+
+fn push_quirk(quirks: &mut Vec<Quirk>, quirk: Quirk) {
+    if !quirks.contains(&quirk) {
+        quirks.push(quirk);
+    }
+}
+
+/// The link MTU that an MSS of `mss` implies, `None` for an MSS of 0.
+pub(crate) fn mtu(version: IpVersion, mss: u16) -> Option<u16> {
+    let headers = match version {
+        IpVersion::V6 => IPV6_HEADER_SIZE + TCP_HEADER_SIZE,
+        _ => IPV4_HEADER_SIZE + TCP_HEADER_SIZE,
+    };
+
+    (mss != 0).then_some(mss)?.checked_add(headers as u16)
+}
+
+/// p0f's `detect_win_multi`: the first of the MSS, the MSS less the
+/// timestamps, the MSS of an Ethernet link, and then the MTU in its various
+/// guises that `window` is a multiple of. `headers` is the size of the IP and
+/// TCP headers with their options.
+pub(crate) fn window_size(
+    version: IpVersion,
+    window: u16,
+    mss: u16,
+    ts1: bool,
+    headers: u16,
+) -> WindowSize {
+    if window == 0 || mss < 100 {
+        return WindowSize::Value(window);
+    }
+
+    let v6 = version == IpVersion::V6;
+    let min_tcp4 = (IPV4_HEADER_SIZE + TCP_HEADER_SIZE) as u16;
+    let min_tcp6 = (IPV6_HEADER_SIZE + TCP_HEADER_SIZE) as u16;
+    let mss_divisors = [
+        Some(mss),
+        ts1.then(|| mss - TS_OPTION_SIZE),
+        Some(ETHERNET_MTU - min_tcp4),
+        Some(ETHERNET_MTU - min_tcp4 - TS_OPTION_SIZE),
+        v6.then_some(ETHERNET_MTU - min_tcp6),
+        v6.then_some(ETHERNET_MTU - min_tcp6 - TS_OPTION_SIZE),
+    ];
+    let mtu_divisors = [
+        mss.checked_add(min_tcp4),
+        mss.checked_add(headers),
+        v6.then(|| mss.checked_add(min_tcp6)).flatten(),
+        Some(ETHERNET_MTU),
+    ];
+    let multiple = |divisor: Option<u16>| {
+        divisor
+            .filter(|&divisor| window.is_multiple_of(divisor))
+            .map(|divisor| window / divisor)
+    };
+
+    // Only one of the two kinds of multiple is ever reported, the MSS first.
+    mss_divisors
+        .into_iter()
+        .find_map(multiple)
+        .map(WindowSize::Mss)
+        .or_else(|| {
+            mtu_divisors
+                .into_iter()
+                .find_map(multiple)
+                .map(WindowSize::Mtu)
+        })
+        .unwrap_or(WindowSize::Value(window))
+}
+
+pub(crate) fn guess_distance(ttl: u8) -> u8 {
+    match ttl {
+        0..=32 => 32 - ttl,
+        33..=64 => 64 - ttl,
+        65..=128 => 128 - ttl,
+        _ => 255 - ttl,
+    }
+}
@@ -0,0 +1,330 @@
+//! Readers for libpcap and pcapng capture files, for fingerprinting hosts
+//! after the fact with [`analyze`].
+
+use std::{
+    fs::File,
+    io::{self, BufReader, Read},
+    path::Path,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    fingerprint::Fingerprinter,
+    hosts::{HostRecord, HostTracker},
+    packet::TcpPacket,
+};
+
+mod ng;
+
+pub use ng::{Interface, PcapngReader};
+
+const MAGIC_MICROS: u32 = 0xa1b2c3d4;
+const MAGIC_NANOS: u32 = 0xa1b23c4d;
+
+const HEADER_SIZE: usize = 24;
+const RECORD_HEADER_SIZE: usize = 16;
+// Far above any snaplen in use, but keeps a corrupt length from allocating
+// gigabytes.
+const MAX_PACKET_SIZE: u32 = 256 * 1024;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LOOP: u32 = 108;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+// Some systems use their DLT_RAW value instead of LINKTYPE_RAW.
+const DLT_RAW: [u32; 2] = [12, 14];
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: [u16; 2] = [0x8100, 0x88a8];
+
+#[derive(Debug, thiserror::Error)]
+pub enum PcapError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("malformed {0}")]
+    Malformed(&'static str),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LinkType {
+    Ethernet,
+    /// Packets start right with their IP header.
+    Raw,
+    /// BSD loopback, with the address family in front of the IP header.
+    Loopback,
+    /// Linux "cooked" capture, e.g. from `tcpdump -i any`.
+    LinuxSll,
+    LinuxSll2,
+    /// A link type this crate doesn't decode, by its `LINKTYPE_` value.
+    Other(u32),
+}
+
+impl LinkType {
+    pub fn from_linktype(linktype: u32) -> LinkType {
+        match linktype {
+            LINKTYPE_NULL | LINKTYPE_LOOP => LinkType::Loopback,
+            LINKTYPE_ETHERNET => LinkType::Ethernet,
+            LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => LinkType::Raw,
+            LINKTYPE_LINUX_SLL => LinkType::LinuxSll,
+            LINKTYPE_LINUX_SLL2 => LinkType::LinuxSll2,
+            linktype if DLT_RAW.contains(&linktype) => LinkType::Raw,
+            linktype => LinkType::Other(linktype),
+        }
+    }
+
+    /// The IPv4 or IPv6 packet inside a frame, if there is one.
+    pub fn ip_payload(self, frame: &[u8]) -> Option<&[u8]> {
+        let (ethertype, payload) = match self {
+            LinkType::Ethernet => {
+                let mut ethertype = u16::from_be_bytes([*frame.get(12)?, *frame.get(13)?]);
+                let mut payload = frame.get(14..)?;
+                while ETHERTYPE_VLAN.contains(&ethertype) {
+                    ethertype = u16::from_be_bytes([*payload.get(2)?, *payload.get(3)?]);
+                    payload = payload.get(4..)?;
+                }
+                (ethertype, payload)
+            }
+            LinkType::LinuxSll => (
+                u16::from_be_bytes([*frame.get(14)?, *frame.get(15)?]),
+                frame.get(16..)?,
+            ),
+            LinkType::LinuxSll2 => (
+                u16::from_be_bytes(frame.get(..2)?.try_into().ok()?),
+                frame.get(20..)?,
+            ),
+            // The address family is in the byte order of the capturing host,
+            // and its value for IPv6 differs between systems, so go by the
+            // IP version instead.
+            LinkType::Loopback | LinkType::Raw => {
+                let payload = match self {
+                    LinkType::Loopback => frame.get(4..)?,
+                    _ => frame,
+                };
+                return matches!(payload.first()? >> 4, 4 | 6).then_some(payload);
+            }
+            LinkType::Other(_) => return None,
+        };
+
+        match ethertype {
+            ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => Some(payload),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Packet {
+    pub time: DateTime<Utc>,
+    /// The captured bytes, which may be cut short by the snaplen.
+    pub data: Vec<u8>,
+    /// Length of the packet on the wire.
+    pub orig_len: u32,
+    pub link_type: LinkType,
+    pub interface: InterfaceId,
+}
+
+/// The capture interface a packet came in on. Interfaces are numbered from
+/// 0 in every pcapng section, so the index alone only tells them apart
+/// within one. libpcap files have a single, unnamed interface.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct InterfaceId {
+    /// Number of the pcapng section, counting from 0.
+    pub section: u32,
+    /// Index of the interface within its section.
+    pub index: u32,
+    /// The `if_name` option of its interface description block, e.g. `eth0`.
+    pub name: Option<String>,
+}
+
+pub struct PcapReader<R: Read> {
+    reader: R,
+    big_endian: bool,
+    nanos: bool,
+    link_type: LinkType,
+    snaplen: u32,
+}
+
+impl PcapReader<BufReader<File>> {
+    pub fn open<T: AsRef<Path>>(path: T) -> Result<Self, PcapError> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> PcapReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, PcapError> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+
+        Self::with_magic(magic, reader)
+    }
+
+    fn with_magic(magic: [u8; 4], mut reader: R) -> Result<Self, PcapError> {
+        let magic = u32::from_le_bytes(magic);
+        let (big_endian, nanos) = match magic {
+            MAGIC_MICROS => (false, false),
+            MAGIC_NANOS => (false, true),
+            _ if magic == MAGIC_MICROS.swap_bytes() => (true, false),
+            _ if magic == MAGIC_NANOS.swap_bytes() => (true, true),
+            _ => return Err(PcapError::Malformed("pcap magic")),
+        };
+
+        let mut header = [0; HEADER_SIZE - 4];
+        reader.read_exact(&mut header)?;
+
+        let mut pcap = PcapReader {
+            reader,
+            big_endian,
+            nanos,
+            link_type: LinkType::Other(0),
+            snaplen: 0,
+        };
+        pcap.snaplen = pcap.u32(&header[12..16]);
+        pcap.link_type = LinkType::from_linktype(pcap.u32(&header[16..20]));
+
+        Ok(pcap)
+    }
+
+    pub fn link_type(&self) -> LinkType {
+        self.link_type
+    }
+
+    pub fn snaplen(&self) -> u32 {
+        self.snaplen
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = bytes.try_into().expect("4 bytes");
+        match self.big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        }
+    }
+
+    fn next_packet(&mut self) -> Result<Option<Packet>, PcapError> {
+        let mut header = [0; RECORD_HEADER_SIZE];
+        if !read_start(&mut self.reader, &mut header)? {
+            return Ok(None);
+        }
+
+        let seconds = self.u32(&header[..4]);
+        let fraction = self.u32(&header[4..8]);
+        let captured = self.u32(&header[8..12]);
+        let orig_len = self.u32(&header[12..16]);
+        if captured > MAX_PACKET_SIZE {
+            return Err(PcapError::Malformed("packet length"));
+        }
+
+        let nanos = match self.nanos {
+            true => fraction,
+            false => fraction.saturating_mul(1000),
+        };
+        let time = DateTime::from_timestamp(seconds.into(), nanos)
+            .ok_or(PcapError::Malformed("packet timestamp"))?;
+
+        let mut data = vec![0; captured as usize];
+        self.reader.read_exact(&mut data)?;
+
+        Ok(Some(Packet {
+            time,
+            data,
+            orig_len,
+            link_type: self.link_type,
+            interface: InterfaceId::default(),
+        }))
+    }
+}
+
+impl<R: Read> Iterator for PcapReader<R> {
+    type Item = Result<Packet, PcapError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_packet().transpose()
+    }
+}
+
+/// Reads either format, telling them apart by their magic number.
+pub enum CaptureReader<R: Read> {
+    Pcap(PcapReader<R>),
+    Pcapng(PcapngReader<R>),
+}
+
+impl CaptureReader<BufReader<File>> {
+    pub fn open<T: AsRef<Path>>(path: T) -> Result<Self, PcapError> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, PcapError> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+
+        if u32::from_le_bytes(magic) == ng::BLOCK_SECTION_HEADER {
+            Ok(CaptureReader::Pcapng(PcapngReader::with_magic(
+                magic, reader,
+            )?))
+        } else {
+            Ok(CaptureReader::Pcap(PcapReader::with_magic(magic, reader)?))
+        }
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = Result<Packet, PcapError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            CaptureReader::Pcap(reader) => reader.next(),
+            CaptureReader::Pcapng(reader) => reader.next(),
+        }
+    }
+}
+
+/// Runs every SYN and SYN+ACK in a capture through `fingerprinter` and
+/// returns what p0f would know about each host at the end of it. Packets
+/// that aren't TCP over IP are skipped.
+pub fn analyze<I>(packets: I, fingerprinter: &Fingerprinter) -> Result<Vec<HostRecord>, PcapError>
+where
+    I: IntoIterator<Item = Result<Packet, PcapError>>,
+{
+    let mut hosts = HostTracker::new(fingerprinter);
+
+    for packet in packets {
+        let packet = packet?;
+        let Some(payload) = packet.link_type.ip_payload(&packet.data) else {
+            continue;
+        };
+        let Ok(tcp) = TcpPacket::parse(payload) else {
+            continue;
+        };
+
+        if let Some(record) = hosts.observe(packet.time, &tcp) {
+            record.link_type = Some(packet.link_type);
+            record.interface = Some(packet.interface);
+        }
+    }
+
+    Ok(hosts.into_records())
+}
+
+/// Fills `buffer`, unless the reader is at its end right away.
+fn read_start<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<bool> {
+    let mut read = 0;
+    while read == 0 {
+        match reader.read(&mut buffer[..1]) {
+            Ok(0) => return Ok(false),
+            Ok(n) => read = n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    reader.read_exact(&mut buffer[1..])?;
+
+    Ok(true)
+}
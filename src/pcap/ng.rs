@@ -0,0 +1,369 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+use chrono::{DateTime, Utc};
+
+use super::{read_start, InterfaceId, LinkType, Packet, PcapError, MAX_PACKET_SIZE};
+
+pub(super) const BLOCK_SECTION_HEADER: u32 = 0x0a0d0d0a;
+const BLOCK_INTERFACE: u32 = 0x00000001;
+// Obsolete, but still written by some old tools.
+const BLOCK_PACKET: u32 = 0x00000002;
+const BLOCK_ENHANCED_PACKET: u32 = 0x00000006;
+
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+const BLOCK_OVERHEAD: usize = 12;
+const PACKET_HEADER_SIZE: usize = 20;
+const MAX_BLOCK_SIZE: u32 = MAX_PACKET_SIZE + 64 * 1024;
+
+const OPT_END: u16 = 0;
+const OPT_IF_NAME: u16 = 2;
+const OPT_IF_TSRESOL: u16 = 9;
+const OPT_IF_TSOFFSET: u16 = 14;
+
+/// A capture interface, from an interface description block.
+#[derive(Clone, Debug)]
+pub struct Interface {
+    pub link_type: LinkType,
+    pub snaplen: u32,
+    /// The `if_name` option, e.g. `eth0`.
+    pub name: Option<String>,
+    resolution: Resolution,
+    offset: i64,
+}
+
+/// Units per second of the timestamps, `10^n` or `2^n`.
+#[derive(Clone, Copy, Debug)]
+enum Resolution {
+    Decimal(u32),
+    Binary(u32),
+}
+
+impl Interface {
+    fn time(&self, timestamp: u64) -> Option<DateTime<Utc>> {
+        let units = match self.resolution {
+            Resolution::Decimal(exponent) => 10u128.checked_pow(exponent)?,
+            Resolution::Binary(exponent) => 2u128.checked_pow(exponent)?,
+        };
+        let timestamp = u128::from(timestamp);
+        let seconds = i64::try_from(timestamp / units).ok()?;
+        let nanos = (timestamp % units) * 1_000_000_000 / units;
+
+        DateTime::from_timestamp(seconds.checked_add(self.offset)?, nanos as u32)
+    }
+}
+
+pub struct PcapngReader<R: Read> {
+    reader: R,
+    big_endian: bool,
+    // Number of the current section.
+    section: u32,
+    interfaces: Vec<Interface>,
+}
+
+impl PcapngReader<BufReader<File>> {
+    pub fn open<T: AsRef<Path>>(path: T) -> Result<Self, PcapError> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> PcapngReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, PcapError> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+
+        Self::with_magic(magic, reader)
+    }
+
+    pub(super) fn with_magic(magic: [u8; 4], reader: R) -> Result<Self, PcapError> {
+        if u32::from_le_bytes(magic) != BLOCK_SECTION_HEADER {
+            return Err(PcapError::Malformed("pcapng magic"));
+        }
+
+        let mut pcapng = PcapngReader {
+            reader,
+            big_endian: false,
+            section: 0,
+            interfaces: Vec::new(),
+        };
+        pcapng.read_block(BLOCK_SECTION_HEADER)?;
+
+        Ok(pcapng)
+    }
+
+    /// The interfaces of the current section, indexed by
+    /// [`InterfaceId::index`].
+    pub fn interfaces(&self) -> &[Interface] {
+        &self.interfaces
+    }
+
+    /// Number of the current section, counting from 0.
+    pub fn section(&self) -> u32 {
+        self.section
+    }
+
+    fn u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = bytes.try_into().expect("2 bytes");
+        match self.big_endian {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        }
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = bytes.try_into().expect("4 bytes");
+        match self.big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        }
+    }
+
+    /// Reads the rest of a block whose type has just been read, and returns
+    /// its body.
+    fn read_block(&mut self, block_type: u32) -> Result<Vec<u8>, PcapError> {
+        let mut length = [0; 4];
+        self.reader.read_exact(&mut length)?;
+
+        // The byte order of a section, length included, follows from the
+        // byte order magic at the start of its header's body.
+        let mut magic = [0; 4];
+        if block_type == BLOCK_SECTION_HEADER {
+            self.reader.read_exact(&mut magic)?;
+            self.big_endian = match u32::from_le_bytes(magic) {
+                BYTE_ORDER_MAGIC => false,
+                magic if magic == BYTE_ORDER_MAGIC.swap_bytes() => true,
+                _ => return Err(PcapError::Malformed("pcapng byte order")),
+            };
+            self.interfaces.clear();
+        }
+
+        let length = self.u32(&length);
+        if length > MAX_BLOCK_SIZE
+            || !length.is_multiple_of(4)
+            || (length as usize) < BLOCK_OVERHEAD
+        {
+            return Err(PcapError::Malformed("block length"));
+        }
+
+        let mut body = vec![0; length as usize - BLOCK_OVERHEAD];
+        let read = match block_type {
+            BLOCK_SECTION_HEADER => {
+                let start = body
+                    .get_mut(..4)
+                    .ok_or(PcapError::Malformed("section header"))?;
+                start.copy_from_slice(&magic);
+                4
+            }
+            _ => 0,
+        };
+        self.reader.read_exact(&mut body[read..])?;
+
+        let mut trailer = [0; 4];
+        self.reader.read_exact(&mut trailer)?;
+        if self.u32(&trailer) != length {
+            return Err(PcapError::Malformed("block length"));
+        }
+
+        Ok(body)
+    }
+
+    fn next_packet(&mut self) -> Result<Option<Packet>, PcapError> {
+        loop {
+            let mut block_type = [0; 4];
+            if !read_start(&mut self.reader, &mut block_type)? {
+                return Ok(None);
+            }
+            let block_type = self.u32(&block_type);
+            let body = self.read_block(block_type)?;
+
+            match block_type {
+                BLOCK_SECTION_HEADER => self.section += 1,
+                BLOCK_INTERFACE => {
+                    let interface = self.interface(&body)?;
+                    self.interfaces.push(interface);
+                }
+                BLOCK_ENHANCED_PACKET | BLOCK_PACKET => return self.packet(block_type, &body),
+                _ => {}
+            }
+        }
+    }
+
+    fn interface(&self, body: &[u8]) -> Result<Interface, PcapError> {
+        if body.len() < 8 {
+            return Err(PcapError::Malformed("interface description"));
+        }
+
+        let mut interface = Interface {
+            link_type: LinkType::from_linktype(self.u16(&body[..2]).into()),
+            snaplen: self.u32(&body[4..8]),
+            name: None,
+            resolution: Resolution::Decimal(6),
+            offset: 0,
+        };
+
+        let mut options = &body[8..];
+        while options.len() >= 4 {
+            let code = self.u16(&options[..2]);
+            let length = usize::from(self.u16(&options[2..4]));
+            let value = options
+                .get(4..4 + length)
+                .ok_or(PcapError::Malformed("interface option"))?;
+
+            match code {
+                OPT_END => break,
+                OPT_IF_NAME => {
+                    interface.name = Some(String::from_utf8_lossy(value).into_owned());
+                }
+                OPT_IF_TSRESOL => {
+                    let resolution = *value
+                        .first()
+                        .ok_or(PcapError::Malformed("interface option"))?;
+                    let exponent = u32::from(resolution & 0x7f);
+                    interface.resolution = match resolution & 0x80 {
+                        0 => Resolution::Decimal(exponent),
+                        _ => Resolution::Binary(exponent),
+                    };
+                }
+                OPT_IF_TSOFFSET => {
+                    let value: [u8; 8] = value
+                        .try_into()
+                        .map_err(|_| PcapError::Malformed("interface option"))?;
+                    interface.offset = match self.big_endian {
+                        true => i64::from_be_bytes(value),
+                        false => i64::from_le_bytes(value),
+                    };
+                }
+                _ => {}
+            }
+
+            options = options
+                .get(4 + length.next_multiple_of(4)..)
+                .unwrap_or_default();
+        }
+
+        Ok(interface)
+    }
+
+    fn packet(&self, block_type: u32, body: &[u8]) -> Result<Option<Packet>, PcapError> {
+        let header = body
+            .get(..PACKET_HEADER_SIZE)
+            .ok_or(PcapError::Malformed("packet block"))?;
+        let interface = match block_type {
+            BLOCK_PACKET => self.u16(&header[..2]).into(),
+            _ => self.u32(&header[..4]),
+        };
+        let info = self
+            .interfaces
+            .get(interface as usize)
+            .ok_or(PcapError::Malformed("packet interface"))?;
+
+        let timestamp =
+            u64::from(self.u32(&header[4..8])) << 32 | u64::from(self.u32(&header[8..12]));
+        let captured = self.u32(&header[12..16]) as usize;
+        let data = body
+            .get(PACKET_HEADER_SIZE..PACKET_HEADER_SIZE + captured)
+            .ok_or(PcapError::Malformed("packet length"))?;
+
+        Ok(Some(Packet {
+            time: info
+                .time(timestamp)
+                .ok_or(PcapError::Malformed("packet timestamp"))?,
+            data: data.to_vec(),
+            orig_len: self.u32(&header[16..20]),
+            link_type: info.link_type,
+            interface: InterfaceId {
+                section: self.section,
+                index: interface,
+                name: info.name.clone(),
+            },
+        }))
+    }
+}
+
+impl<R: Read> Iterator for PcapngReader<R> {
+    type Item = Result<Packet, PcapError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_packet().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+        let length = (BLOCK_OVERHEAD + body.len().next_multiple_of(4)) as u32;
+
+        let mut block = block_type.to_le_bytes().to_vec();
+        block.extend_from_slice(&length.to_le_bytes());
+        block.extend_from_slice(body);
+        block.resize(length as usize - 4, 0);
+        block.extend_from_slice(&length.to_le_bytes());
+        block
+    }
+
+    fn section() -> Vec<u8> {
+        let mut body = BYTE_ORDER_MAGIC.to_le_bytes().to_vec();
+        body.extend_from_slice(&[1, 0, 0, 0]);
+        body.extend_from_slice(&[0xff; 8]);
+        block(BLOCK_SECTION_HEADER, &body)
+    }
+
+    fn interface(name: &str) -> Vec<u8> {
+        let mut body = vec![1, 0, 0, 0, 0, 0, 1, 0];
+        body.extend_from_slice(&OPT_IF_NAME.to_le_bytes());
+        body.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        body.extend_from_slice(name.as_bytes());
+        body.resize(body.len().next_multiple_of(4), 0);
+        block(BLOCK_INTERFACE, &body)
+    }
+
+    fn packet(interface: u32) -> Vec<u8> {
+        let mut body = interface.to_le_bytes().to_vec();
+        body.extend_from_slice(&[0; 8]);
+        body.extend_from_slice(&4u32.to_le_bytes());
+        body.extend_from_slice(&4u32.to_le_bytes());
+        body.extend_from_slice(&[0x45, 0, 0, 4]);
+        block(BLOCK_ENHANCED_PACKET, &body)
+    }
+
+    #[test]
+    fn sections() {
+        let capture = [
+            section(),
+            interface("eth0"),
+            interface("wlan0"),
+            packet(1),
+            section(),
+            interface("lo"),
+            packet(0),
+        ]
+        .concat();
+
+        let interfaces = PcapngReader::new(&capture[..])
+            .unwrap()
+            .map(|packet| packet.unwrap().interface)
+            .collect::<Vec<_>>();
+        let id = |section, index, name: &str| InterfaceId {
+            section,
+            index,
+            name: Some(name.to_string()),
+        };
+        assert_eq!(interfaces, [id(0, 1, "wlan0"), id(1, 0, "lo")]);
+    }
+
+    #[test]
+    fn unknown_interface() {
+        let capture = [section(), interface("eth0"), section(), packet(0)].concat();
+
+        let mut reader = PcapngReader::new(&capture[..]).unwrap();
+        assert!(matches!(
+            reader.next(),
+            Some(Err(PcapError::Malformed("packet interface")))
+        ));
+    }
+}
@@ -0,0 +1,296 @@
+use std::{
+    io,
+    net::IpAddr,
+    path::Path,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use crate::{Error, P0f, P0fBuilder, Response};
+
+// p0f's own default for `-S`, the number of simultaneous API connections.
+pub(crate) const DEFAULT_API_MAX_CONN: usize = 20;
+const DEFAULT_SIZE: usize = 4;
+
+#[derive(Clone, Debug)]
+pub struct PoolConfig {
+    /// Number of connections the pool keeps open to the API socket.
+    pub size: usize,
+    /// The `-S` limit the daemon was started with. p0f drops any connection
+    /// beyond this, so `size` is not allowed to exceed it.
+    pub api_max_conn: usize,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            size: DEFAULT_SIZE,
+            api_max_conn: DEFAULT_API_MAX_CONN,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PoolStats {
+    pub size: usize,
+    pub open: usize,
+    pub idle: usize,
+    pub in_use: usize,
+    pub waits: u64,
+    pub total_wait: Duration,
+    pub max_wait: Duration,
+}
+
+#[derive(Clone)]
+pub struct P0fPool {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    builder: P0fBuilder,
+    config: PoolConfig,
+    state: Mutex<State>,
+    available: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    idle: Vec<P0f>,
+    open: usize,
+    waits: u64,
+    total_wait: Duration,
+    max_wait: Duration,
+}
+
+impl P0fPool {
+    pub fn new<T: AsRef<Path>>(path: T) -> Result<Self, Error> {
+        Self::with_config(path, PoolConfig::default())
+    }
+
+    pub fn with_config<T: AsRef<Path>>(path: T, config: PoolConfig) -> Result<Self, Error> {
+        Self::with_builder(P0fBuilder::new(path), config)
+    }
+
+    /// Opens every connection with `builder`, so pooled clients get its
+    /// timeouts, retry policy, decode mode and layout. Each one probes the
+    /// daemon if the layout is
+    /// [`ResponseLayout::Auto`](crate::protocol::ResponseLayout::Auto).
+    pub fn with_builder(builder: P0fBuilder, config: PoolConfig) -> Result<Self, Error> {
+        if config.size == 0 {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "pool size must be at least 1",
+            )));
+        }
+        if config.size > config.api_max_conn {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "pool size {} exceeds api_max_conn {}",
+                    config.size, config.api_max_conn
+                ),
+            )));
+        }
+
+        let idle = (0..config.size)
+            .map(|_| builder.clone().connect())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(P0fPool {
+            inner: Arc::new(Inner {
+                builder,
+                state: Mutex::new(State {
+                    open: idle.len(),
+                    idle,
+                    ..Default::default()
+                }),
+                config,
+                available: Condvar::new(),
+            }),
+        })
+    }
+
+    pub fn get(&self) -> Result<PooledP0f, Error> {
+        let started = Instant::now();
+        let mut waited = false;
+        let mut state = self.inner.lock();
+
+        loop {
+            if let Some(p0f) = state.idle.pop() {
+                state.record_wait(waited, started.elapsed());
+                return Ok(self.pooled(p0f));
+            }
+
+            if state.open < self.inner.config.size {
+                state.open += 1;
+                state.record_wait(waited, started.elapsed());
+                drop(state);
+
+                return match self.inner.builder.clone().connect() {
+                    Ok(p0f) => Ok(self.pooled(p0f)),
+                    Err(err) => {
+                        self.inner.release(None);
+                        Err(err)
+                    }
+                };
+            }
+
+            waited = true;
+            state = self
+                .inner
+                .available
+                .wait(state)
+                .unwrap_or_else(|err| err.into_inner());
+        }
+    }
+
+    pub fn query<T: Into<IpAddr>>(&self, address: T) -> Result<Option<Response>, Error> {
+        self.get()?.query(address)
+    }
+
+    pub fn config(&self) -> &PoolConfig {
+        &self.inner.config
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let state = self.inner.lock();
+
+        PoolStats {
+            size: self.inner.config.size,
+            open: state.open,
+            idle: state.idle.len(),
+            in_use: state.open - state.idle.len(),
+            waits: state.waits,
+            total_wait: state.total_wait,
+            max_wait: state.max_wait,
+        }
+    }
+
+    fn pooled(&self, p0f: P0f) -> PooledP0f {
+        PooledP0f {
+            p0f: Some(p0f),
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl Inner {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn release(&self, p0f: Option<P0f>) {
+        let mut state = self.lock();
+        match p0f {
+            Some(p0f) => state.idle.push(p0f),
+            None => state.open -= 1,
+        }
+        drop(state);
+
+        self.available.notify_one();
+    }
+}
+
+impl State {
+    fn record_wait(&mut self, waited: bool, elapsed: Duration) {
+        if waited {
+            self.waits += 1;
+            self.total_wait += elapsed;
+            self.max_wait = self.max_wait.max(elapsed);
+        }
+    }
+}
+
+pub struct PooledP0f {
+    p0f: Option<P0f>,
+    inner: Arc<Inner>,
+}
+
+impl PooledP0f {
+    pub fn query<T: Into<IpAddr>>(&mut self, address: T) -> Result<Option<Response>, Error> {
+        let Some(p0f) = self.p0f.as_mut() else {
+            return Err(Error::Io(io::ErrorKind::NotConnected.into()));
+        };
+
+        let result = p0f.query(address);
+        if let Err(err) = &result {
+            if err.breaks_connection() {
+                // Whatever is left on this socket can't be trusted anymore, so
+                // give the slot back and let the next checkout reconnect.
+                self.p0f = None;
+            }
+        }
+
+        result
+    }
+}
+
+impl Drop for PooledP0f {
+    fn drop(&mut self) {
+        self.inner.release(self.p0f.take());
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use std::{net::Ipv4Addr, time::Duration};
+
+    use super::*;
+    use crate::{
+        protocol::ResponseLayout,
+        testing::{Fault, MockP0fServer},
+        RetryPolicy,
+    };
+
+    #[test]
+    fn builder() {
+        let server = MockP0fServer::with_layout(ResponseLayout::Mtu, |address| {
+            Some(Response::for_address(address))
+        })
+        .unwrap();
+        let builder = P0fBuilder::new(server.path())
+            .layout(ResponseLayout::Auto)
+            .read_timeout(Duration::from_millis(50))
+            .retry_policy(RetryPolicy::never());
+        let pool = P0fPool::with_builder(
+            builder,
+            PoolConfig {
+                size: 1,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        for address in [Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2)] {
+            let response = pool.query(address).unwrap().unwrap();
+            assert_eq!(response.os_name, Some(address.to_string()));
+            assert_eq!(response.link_mtu, Some(0));
+        }
+
+        let fault = Fault::Delay(Duration::from_millis(200));
+        server.fail_next(fault.clone());
+        assert!(fault.is_expected(&pool.query(Ipv4Addr::LOCALHOST).unwrap_err()));
+        // The connection that timed out is replaced by a new one, which
+        // probes the layout again.
+        assert!(pool.query(Ipv4Addr::LOCALHOST).unwrap().is_some());
+    }
+
+    #[test]
+    fn size() {
+        let server = MockP0fServer::from_fn(|_| None).unwrap();
+        let config = |size| PoolConfig {
+            size,
+            api_max_conn: 2,
+        };
+
+        assert!(P0fPool::with_config(server.path(), config(0)).is_err());
+        assert!(P0fPool::with_config(server.path(), config(3)).is_err());
+        assert_eq!(
+            P0fPool::with_config(server.path(), config(2))
+                .unwrap()
+                .stats()
+                .open,
+            2
+        );
+    }
+}
@@ -0,0 +1,572 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::{BadSw, DecodeMode, Error, OsMatchQuality, Response};
+
+pub const REQUEST_MAGIC: u32 = 0x50304601;
+pub const RESPONSE_MAGIC: u32 = 0x50304602;
+
+pub const REQUEST_SIZE: usize = 21;
+pub const RESPONSE_SIZE: usize = 232;
+pub const RESPONSE_SIZE_MTU: usize = 234;
+
+pub const STR_MAX: usize = 31;
+pub const STR_SIZE: usize = STR_MAX + 1;
+
+pub const STATUS_BADQUERY: u32 = 0x00;
+pub const STATUS_OK: u32 = 0x10;
+pub const STATUS_NOMATCH: u32 = 0x20;
+
+pub const ADDRESS_IPV4: u8 = 0x04;
+pub const ADDRESS_IPV6: u8 = 0x06;
+
+pub const MATCH_NORMAL: u8 = 0x00;
+pub const MATCH_FUZZY: u8 = 0x01;
+pub const MATCH_GENERIC: u8 = 0x02;
+pub const MATCH_FUZZY_GENERIC: u8 = 0x03;
+
+const BAD_SW_NONE: u8 = 0x00;
+const BAD_SW_OS_DIFFERENCE: u8 = 0x01;
+const BAD_SW_OUTRIGHT_MISMATCH: u8 = 0x02;
+
+// Enough of two back-to-back responses to see where the second one starts.
+pub(crate) const PROBE_SIZE: usize = RESPONSE_SIZE + 4;
+
+const PROBE_ADDRESS: Ipv4Addr = Ipv4Addr::UNSPECIFIED;
+
+const SECS_PER_MIN: u64 = 60;
+const SECS_PER_DAY: u64 = 86400;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Request {
+    pub address: IpAddr,
+}
+
+impl Request {
+    pub fn new<T: Into<IpAddr>>(address: T) -> Self {
+        Request {
+            address: address.into(),
+        }
+    }
+
+    pub fn encode(&self) -> [u8; REQUEST_SIZE] {
+        let mut request = [0; REQUEST_SIZE];
+        request[..4].copy_from_slice(&REQUEST_MAGIC.to_ne_bytes());
+
+        match self.address {
+            IpAddr::V4(address) => {
+                request[4] = ADDRESS_IPV4;
+                request[5..9].copy_from_slice(&address.octets());
+            }
+            IpAddr::V6(address) => {
+                request[4] = ADDRESS_IPV6;
+                request[5..].copy_from_slice(&address.octets());
+            }
+        }
+
+        request
+    }
+
+    pub fn decode(buffer: &[u8]) -> Result<Request, Error> {
+        let mut request = BufferReader::new(buffer);
+
+        if request.read_u32("magic")? != REQUEST_MAGIC {
+            return Err(Error::InvalidMagic);
+        }
+        let address_type = request.read_u8("addr_type")?;
+        let address = request.read_array::<16>("addr")?;
+
+        let address = match address_type {
+            ADDRESS_IPV4 => IpAddr::V4(Ipv4Addr::new(
+                address[0], address[1], address[2], address[3],
+            )),
+            ADDRESS_IPV6 => IpAddr::V6(Ipv6Addr::from(*address)),
+            // This is what p0f itself answers to an unknown address type.
+            _ => return Err(Error::BadQuery),
+        };
+
+        Ok(Request { address })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ResponseLayout {
+    /// Work the layout out from the daemon (or the buffer length when
+    /// decoding).
+    Auto,
+    /// The 232 byte response of a stock p0f build.
+    Stock,
+    /// The 234 byte response of a p0f build patched to report `link_mtu`.
+    Mtu,
+}
+
+impl ResponseLayout {
+    pub fn size(self) -> Option<usize> {
+        match self {
+            ResponseLayout::Auto => None,
+            ResponseLayout::Stock => Some(RESPONSE_SIZE),
+            ResponseLayout::Mtu => Some(RESPONSE_SIZE_MTU),
+        }
+    }
+
+    fn of_len(len: usize) -> ResponseLayout {
+        if len >= RESPONSE_SIZE_MTU {
+            ResponseLayout::Mtu
+        } else {
+            ResponseLayout::Stock
+        }
+    }
+
+    fn of_response(response: &Response) -> ResponseLayout {
+        match response.link_mtu {
+            Some(_) => ResponseLayout::Mtu,
+            None => ResponseLayout::Stock,
+        }
+    }
+
+    /// Tells the layouts apart from the first [`PROBE_SIZE`] bytes the daemon
+    /// sent back for two pipelined requests: with the stock layout the second
+    /// response's magic starts right at [`RESPONSE_SIZE`].
+    pub(crate) fn from_probe(probe: &[u8; PROBE_SIZE]) -> Result<ResponseLayout, Error> {
+        if probe[..4] != RESPONSE_MAGIC.to_ne_bytes() {
+            return Err(Error::InvalidMagic);
+        }
+
+        if probe[RESPONSE_SIZE..] == RESPONSE_MAGIC.to_ne_bytes() {
+            Ok(ResponseLayout::Stock)
+        } else {
+            Ok(ResponseLayout::Mtu)
+        }
+    }
+}
+
+/// Two identical requests for an address p0f won't know about, sent back to
+/// back to work out the response layout with [`ResponseLayout::from_probe`].
+pub(crate) fn probe_request() -> [u8; 2 * REQUEST_SIZE] {
+    let request = Request::new(PROBE_ADDRESS).encode();
+
+    let mut probe = [0; 2 * REQUEST_SIZE];
+    probe[..REQUEST_SIZE].copy_from_slice(&request);
+    probe[REQUEST_SIZE..].copy_from_slice(&request);
+
+    probe
+}
+
+/// Whether `request` could be one of the two in a [`probe_request`].
+#[cfg(feature = "testing")]
+pub(crate) fn is_probe(request: &Request) -> bool {
+    request.address == IpAddr::V4(PROBE_ADDRESS)
+}
+
+impl Default for ResponseLayout {
+    #[cfg(not(feature = "p0f-mtu"))]
+    fn default() -> Self {
+        ResponseLayout::Stock
+    }
+
+    #[cfg(feature = "p0f-mtu")]
+    fn default() -> Self {
+        ResponseLayout::Mtu
+    }
+}
+
+impl Response {
+    pub fn decode(buffer: &[u8]) -> Result<Response, Error> {
+        decode_response(buffer, ResponseLayout::Auto, DecodeMode::Strict)?.ok_or(Error::NoMatch)
+    }
+
+    /// Encodes the response in the MTU layout if it carries a `link_mtu`,
+    /// and in the stock layout otherwise.
+    pub fn encode(&self) -> Vec<u8> {
+        encode_response(Some(self), ResponseLayout::Auto)
+    }
+}
+
+/// Decodes a full API response, mapping `STATUS_NOMATCH` to `None` the same
+/// way [`P0f::query`](crate::P0f::query) does. [`ResponseLayout::Auto`] picks
+/// the layout from the length of `buffer`.
+pub fn decode_response(
+    buffer: &[u8],
+    layout: ResponseLayout,
+    mode: DecodeMode,
+) -> Result<Option<Response>, Error> {
+    let layout = match layout {
+        ResponseLayout::Auto => ResponseLayout::of_len(buffer.len()),
+        layout => layout,
+    };
+    let mut response = BufferReader::new(buffer);
+
+    if response.read_u32("magic")? != RESPONSE_MAGIC {
+        return Err(Error::InvalidMagic);
+    }
+    match response.read_u32("status")? {
+        STATUS_BADQUERY => return Err(Error::BadQuery),
+        STATUS_OK => {}
+        STATUS_NOMATCH => return Ok(None),
+        status => return Err(Error::UnknownStatus(status)),
+    }
+
+    let first_seen = timestamp(response.read_u32("first_seen")?, "first_seen")?;
+    let last_seen = timestamp(response.read_u32("last_seen")?, "last_seen")?;
+    let total_conn = response.read_u32("total_conn")?;
+
+    let uptime_min = match response.read_u32("uptime_min")? {
+        0 => None,
+        uptime => Some(Duration::from_secs(uptime as u64 * SECS_PER_MIN)),
+    };
+    let up_mod_days = response.read_u32("up_mod_days")?;
+    let up_mod_days = Duration::from_secs(up_mod_days as u64 * SECS_PER_DAY);
+
+    let last_nat = match response.read_u32("last_nat")? {
+        0 => None,
+        last_nat => Some(timestamp(last_nat, "last_nat")?),
+    };
+    let last_chg = match response.read_u32("last_chg")? {
+        0 => None,
+        last_chg => Some(timestamp(last_chg, "last_chg")?),
+    };
+    let distance = match i16::from_ne_bytes(*response.read_array("distance")?) {
+        -1 => None,
+        distance => Some(distance),
+    };
+
+    let bad_sw = match response.read_u8("bad_sw")? {
+        BAD_SW_NONE => None,
+        BAD_SW_OS_DIFFERENCE => Some(BadSw::OsDifference),
+        BAD_SW_OUTRIGHT_MISMATCH => Some(BadSw::OutrightMismatch),
+        value => Some(BadSw::Unknown(mode.check("bad_sw", value)?)),
+    };
+    let os_match_q = match response.read_u8("os_match_q")? {
+        MATCH_NORMAL => OsMatchQuality::Normal,
+        MATCH_FUZZY => OsMatchQuality::Fuzzy,
+        MATCH_GENERIC => OsMatchQuality::Generic,
+        MATCH_FUZZY_GENERIC => OsMatchQuality::FuzzyGeneric,
+        value => OsMatchQuality::Unknown(mode.check("os_match_q", value)?),
+    };
+
+    let os_name = response.read_str("os_name")?;
+    let os_flavor = response.read_str("os_flavor")?;
+    let http_name = response.read_str("http_name")?;
+    let http_flavor = response.read_str("http_flavor")?;
+
+    let link_mtu = match layout {
+        ResponseLayout::Mtu => Some(u16::from_ne_bytes(*response.read_array("mtu")?)),
+        _ => None,
+    };
+
+    let link_type = response.read_str("link_type")?;
+    let language = response.read_str("language")?;
+
+    Ok(Some(Response {
+        first_seen,
+        last_seen,
+        total_conn,
+        uptime_min,
+        up_mod_days,
+        last_nat,
+        last_chg,
+        distance,
+        bad_sw,
+        os_match_q,
+        os_name,
+        os_flavor,
+        http_name,
+        http_flavor,
+        link_mtu,
+        link_type,
+        language,
+    }))
+}
+
+/// Encodes a `STATUS_OK` response, or `STATUS_NOMATCH` for `None`.
+/// [`ResponseLayout::Auto`] picks the layout from `link_mtu`, the same way
+/// [`Response::encode`] does.
+pub fn encode_response(response: Option<&Response>, layout: ResponseLayout) -> Vec<u8> {
+    let Some(response) = response else {
+        return encode_status(STATUS_NOMATCH, layout);
+    };
+    let layout = match layout {
+        ResponseLayout::Auto => ResponseLayout::of_response(response),
+        layout => layout,
+    };
+
+    let mut buffer = vec![0; layout.size().unwrap_or(RESPONSE_SIZE)];
+    let mut writer = BufferWriter::new(&mut buffer);
+
+    writer.write_u32(RESPONSE_MAGIC);
+    writer.write_u32(STATUS_OK);
+    writer.write_u32(unix_time(&response.first_seen));
+    writer.write_u32(unix_time(&response.last_seen));
+    writer.write_u32(response.total_conn);
+    writer.write_u32(whole_units(
+        response.uptime_min.unwrap_or_default(),
+        SECS_PER_MIN,
+    ));
+    writer.write_u32(whole_units(response.up_mod_days, SECS_PER_DAY));
+    writer.write_u32(response.last_nat.as_ref().map_or(0, unix_time));
+    writer.write_u32(response.last_chg.as_ref().map_or(0, unix_time));
+    writer.write(&response.distance.unwrap_or(-1).to_ne_bytes());
+    writer.write(&[match response.bad_sw {
+        None => BAD_SW_NONE,
+        Some(BadSw::OsDifference) => BAD_SW_OS_DIFFERENCE,
+        Some(BadSw::OutrightMismatch) => BAD_SW_OUTRIGHT_MISMATCH,
+        Some(BadSw::Unknown(value)) => value,
+    }]);
+    writer.write(&[match response.os_match_q {
+        OsMatchQuality::Normal => MATCH_NORMAL,
+        OsMatchQuality::Fuzzy => MATCH_FUZZY,
+        OsMatchQuality::Generic => MATCH_GENERIC,
+        OsMatchQuality::FuzzyGeneric => MATCH_FUZZY_GENERIC,
+        OsMatchQuality::Unknown(value) => value,
+    }]);
+    writer.write_str(response.os_name.as_deref());
+    writer.write_str(response.os_flavor.as_deref());
+    writer.write_str(response.http_name.as_deref());
+    writer.write_str(response.http_flavor.as_deref());
+    if layout == ResponseLayout::Mtu {
+        writer.write(&response.link_mtu.unwrap_or_default().to_ne_bytes());
+    }
+    writer.write_str(response.link_type.as_deref());
+    writer.write_str(response.language.as_deref());
+
+    buffer
+}
+
+/// Encodes a response that carries nothing but a status, like p0f sends for
+/// `STATUS_BADQUERY` and `STATUS_NOMATCH`. [`ResponseLayout::Auto`] is
+/// treated as the stock layout.
+pub fn encode_status(status: u32, layout: ResponseLayout) -> Vec<u8> {
+    let mut buffer = vec![0; layout.size().unwrap_or(RESPONSE_SIZE)];
+    buffer[..4].copy_from_slice(&RESPONSE_MAGIC.to_ne_bytes());
+    buffer[4..8].copy_from_slice(&status.to_ne_bytes());
+
+    buffer
+}
+
+fn timestamp(value: u32, field: &'static str) -> Result<DateTime<Utc>, Error> {
+    DateTime::from_timestamp(value as i64, 0).ok_or(Error::TimestampOutOfRange(field))
+}
+
+fn unix_time(time: &DateTime<Utc>) -> u32 {
+    time.timestamp().clamp(0, u32::MAX as i64) as u32
+}
+
+fn whole_units(duration: Duration, unit: u64) -> u32 {
+    (duration.as_secs() / unit).min(u32::MAX as u64) as u32
+}
+
+struct BufferReader<'a> {
+    buffer: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BufferReader<'a> {
+    fn new(buffer: &'a [u8]) -> Self {
+        BufferReader { buffer, pos: 0 }
+    }
+
+    fn read_array<const N: usize>(&mut self, field: &'static str) -> Result<&'a [u8; N], Error> {
+        if self.pos + N <= self.buffer.len() {
+            let slice = &self.buffer[self.pos..self.pos + N];
+            self.pos += N;
+            // SAFETY: We know that the slice has exactly N elements
+            Ok(slice.try_into().unwrap())
+        } else {
+            Err(Error::MissingData(field))
+        }
+    }
+
+    fn read_u8(&mut self, field: &'static str) -> Result<u8, Error> {
+        Ok(self.read_array::<1>(field)?[0])
+    }
+
+    fn read_u32(&mut self, field: &'static str) -> Result<u32, Error> {
+        Ok(u32::from_ne_bytes(*self.read_array(field)?))
+    }
+
+    fn read_str(&mut self, field: &'static str) -> Result<Option<String>, Error> {
+        let buffer = self.read_array::<STR_SIZE>(field)?;
+        let len = buffer.iter().position(|&b| b == 0).unwrap_or(STR_SIZE);
+
+        Ok(match len {
+            0 => None,
+            len => Some(String::from_utf8_lossy(&buffer[..len]).into_owned()),
+        })
+    }
+}
+
+struct BufferWriter<'a> {
+    buffer: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> BufferWriter<'a> {
+    fn new(buffer: &'a mut [u8]) -> Self {
+        BufferWriter { buffer, pos: 0 }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.buffer[self.pos..self.pos + bytes.len()].copy_from_slice(bytes);
+        self.pos += bytes.len();
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.write(&value.to_ne_bytes());
+    }
+
+    fn write_str(&mut self, value: Option<&str>) {
+        let mut field = [0; STR_SIZE];
+        if let Some(value) = value {
+            // p0f keeps at most STR_MAX bytes plus the terminating NUL.
+            let len = value.len().min(STR_MAX);
+            field[..len].copy_from_slice(&value.as_bytes()[..len]);
+        }
+
+        self.write(&field);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response() -> Response {
+        Response {
+            last_nat: DateTime::from_timestamp(1_700_000_300, 0),
+            bad_sw: Some(BadSw::OsDifference),
+            os_match_q: OsMatchQuality::Fuzzy,
+            http_name: Some("Firefox".to_string()),
+            http_flavor: Some("10.x or newer".to_string()),
+            language: Some("English".to_string()),
+            ..Response::for_address(Ipv4Addr::new(10, 0, 0, 1).into())
+        }
+    }
+
+    #[test]
+    fn request() {
+        for address in [
+            IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+            IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
+        ] {
+            let request = Request::new(address).encode();
+            assert_eq!(request[..4], REQUEST_MAGIC.to_ne_bytes());
+            assert_eq!(Request::decode(&request).unwrap().address, address);
+        }
+    }
+
+    #[test]
+    fn malformed_request() {
+        let mut request = Request::new(Ipv4Addr::LOCALHOST).encode();
+
+        assert!(matches!(
+            Request::decode(&request[..REQUEST_SIZE - 1]),
+            Err(Error::MissingData("addr"))
+        ));
+
+        request[4] = 0x05;
+        assert!(matches!(Request::decode(&request), Err(Error::BadQuery)));
+
+        request[..4].copy_from_slice(&RESPONSE_MAGIC.to_ne_bytes());
+        assert!(matches!(
+            Request::decode(&request),
+            Err(Error::InvalidMagic)
+        ));
+    }
+
+    #[test]
+    fn stock_round_trip() {
+        let response = response();
+        let encoded = encode_response(Some(&response), ResponseLayout::Stock);
+        assert_eq!(encoded.len(), RESPONSE_SIZE);
+
+        let decoded = decode_response(&encoded, ResponseLayout::Auto, DecodeMode::Strict)
+            .unwrap()
+            .unwrap();
+        assert_eq!(decoded.first_seen, response.first_seen);
+        assert_eq!(decoded.last_nat, response.last_nat);
+        assert_eq!(decoded.uptime_min, response.uptime_min);
+        assert_eq!(decoded.distance, response.distance);
+        assert_eq!(decoded.bad_sw, response.bad_sw);
+        assert_eq!(decoded.language, response.language);
+        assert_eq!(decoded.link_mtu, None);
+        assert_eq!(decoded.encode(), encoded);
+    }
+
+    #[test]
+    fn mtu_round_trip() {
+        let response = Response {
+            link_mtu: Some(1492),
+            ..response()
+        };
+        let encoded = response.encode();
+        assert_eq!(encoded.len(), RESPONSE_SIZE_MTU);
+
+        let decoded = decode_response(&encoded, ResponseLayout::Mtu, DecodeMode::Strict)
+            .unwrap()
+            .unwrap();
+        assert_eq!(decoded.link_mtu, Some(1492));
+        assert_eq!(decoded.link_type, response.link_type);
+        assert_eq!(decoded.language, response.language);
+        assert_eq!(decoded.encode(), encoded);
+    }
+
+    #[test]
+    fn status() {
+        for layout in [ResponseLayout::Stock, ResponseLayout::Mtu] {
+            let decode = |status| {
+                decode_response(&encode_status(status, layout), layout, DecodeMode::Strict)
+            };
+
+            assert!(matches!(decode(STATUS_NOMATCH), Ok(None)));
+            assert!(matches!(decode(STATUS_BADQUERY), Err(Error::BadQuery)));
+            assert!(matches!(decode(0x30), Err(Error::UnknownStatus(0x30))));
+        }
+    }
+
+    #[test]
+    fn malformed_response() {
+        let mut response = response().encode();
+
+        assert!(matches!(
+            decode_response(&response[..100], ResponseLayout::Stock, DecodeMode::Strict),
+            Err(Error::MissingData("os_flavor"))
+        ));
+        assert!(matches!(
+            decode_response(
+                &response[..RESPONSE_SIZE - 1],
+                ResponseLayout::Auto,
+                DecodeMode::Strict
+            ),
+            Err(Error::MissingData("language"))
+        ));
+
+        response[..4].copy_from_slice(&REQUEST_MAGIC.to_ne_bytes());
+        assert!(matches!(
+            decode_response(&response, ResponseLayout::Stock, DecodeMode::Strict),
+            Err(Error::InvalidMagic)
+        ));
+    }
+
+    #[test]
+    fn unknown_values() {
+        let mut response = response().encode();
+        // os_match_q comes after nine u32 fields, distance and bad_sw.
+        response[39] = 0x07;
+
+        assert!(matches!(
+            decode_response(&response, ResponseLayout::Stock, DecodeMode::Strict),
+            Err(Error::UnknownEnumValue {
+                field: "os_match_q",
+                value: 0x07
+            })
+        ));
+        let decoded = decode_response(&response, ResponseLayout::Stock, DecodeMode::Lenient)
+            .unwrap()
+            .unwrap();
+        assert!(matches!(decoded.os_match_q, OsMatchQuality::Unknown(0x07)));
+    }
+}
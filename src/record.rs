@@ -0,0 +1,225 @@
+//! Recording of API sessions, for replaying them later through
+//! [`MockP0fServer::from_recording`](crate::testing::MockP0fServer::from_recording).
+//!
+//! A recording is a UTF-8 text file with one exchange per line:
+//!
+//! ```text
+//! # p0f-rs recording v1
+//! 2024-01-01T12:00:00.000000Z 01463050040a000001000000000000000000000000 024630501000000065a1...
+//! ```
+//!
+//! Each line holds three fields separated by a single space: the time the
+//! request was sent as an RFC 3339 timestamp in UTC, the 21 byte request and
+//! the raw response (232 or 234 bytes, depending on the daemon's layout), both
+//! as lowercase hex. Multi-byte integers inside the request and response are
+//! in the recording host's byte order, just like on the socket. Empty lines
+//! and lines starting with `#` are ignored.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    net::IpAddr,
+    path::Path,
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::{
+    protocol::{self, Request, ResponseLayout, REQUEST_SIZE, RESPONSE_SIZE, RESPONSE_SIZE_MTU},
+    DecodeMode, Error, P0f, Response,
+};
+
+const HEADER: &str = "# p0f-rs recording v1";
+
+#[derive(Debug, thiserror::Error)]
+pub enum RecordError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("line {line}: {reason}")]
+    Malformed { line: usize, reason: &'static str },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Exchange {
+    pub time: DateTime<Utc>,
+    pub request: [u8; REQUEST_SIZE],
+    pub response: Vec<u8>,
+}
+
+impl Exchange {
+    pub fn request(&self) -> Result<Request, Error> {
+        Request::decode(&self.request)
+    }
+
+    pub fn response(&self, mode: DecodeMode) -> Result<Option<Response>, Error> {
+        protocol::decode_response(&self.response, ResponseLayout::Auto, mode)
+    }
+}
+
+pub struct RecordWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> RecordWriter<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writeln!(writer, "{HEADER}")?;
+
+        Ok(RecordWriter { writer })
+    }
+
+    pub fn write(&mut self, exchange: &Exchange) -> io::Result<()> {
+        writeln!(
+            self.writer,
+            "{} {} {}",
+            exchange.time.to_rfc3339_opts(SecondsFormat::Micros, true),
+            hex(&exchange.request),
+            hex(&exchange.response),
+        )
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+pub struct RecordReader<R: BufRead> {
+    lines: io::Lines<R>,
+    line: usize,
+}
+
+impl<R: BufRead> RecordReader<R> {
+    pub fn new(reader: R) -> Self {
+        RecordReader {
+            lines: reader.lines(),
+            line: 0,
+        }
+    }
+}
+
+impl RecordReader<BufReader<File>> {
+    pub fn open<T: AsRef<Path>>(path: T) -> io::Result<Self> {
+        Ok(Self::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+impl<R: BufRead> Iterator for RecordReader<R> {
+    type Item = Result<Exchange, RecordError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(err) => return Some(Err(err.into())),
+            };
+            self.line += 1;
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            return Some(parse_line(line).map_err(|reason| RecordError::Malformed {
+                line: self.line,
+                reason,
+            }));
+        }
+    }
+}
+
+pub fn read_recording<T: AsRef<Path>>(path: T) -> Result<Vec<Exchange>, RecordError> {
+    RecordReader::open(path)?.collect()
+}
+
+/// Wraps a [`P0f`] and writes every exchange that got a response from the
+/// daemon, including `STATUS_BADQUERY` and `STATUS_NOMATCH`, to a recording.
+pub struct RecordingP0f<W: Write> {
+    p0f: P0f,
+    writer: RecordWriter<W>,
+}
+
+impl RecordingP0f<BufWriter<File>> {
+    pub fn create<T: AsRef<Path>>(p0f: P0f, path: T) -> io::Result<Self> {
+        Self::new(p0f, BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> RecordingP0f<W> {
+    pub fn new(p0f: P0f, writer: W) -> io::Result<Self> {
+        Ok(RecordingP0f {
+            p0f,
+            writer: RecordWriter::new(writer)?,
+        })
+    }
+
+    pub fn query<T: Into<IpAddr>>(&mut self, address: T) -> Result<Option<Response>, Error> {
+        let request = Request::new(address);
+        let time = Utc::now();
+        let response = self.p0f.query_raw(request.address)?;
+
+        self.writer.write(&Exchange {
+            time,
+            request: request.encode(),
+            response: response.clone(),
+        })?;
+
+        self.p0f.decode(&response)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn get_ref(&self) -> &P0f {
+        &self.p0f
+    }
+
+    pub fn into_inner(self) -> (P0f, W) {
+        (self.p0f, self.writer.into_inner())
+    }
+}
+
+fn parse_line(line: &str) -> Result<Exchange, &'static str> {
+    let mut fields = line.split(' ');
+    let (Some(time), Some(request), Some(response), None) =
+        (fields.next(), fields.next(), fields.next(), fields.next())
+    else {
+        return Err("expected time, request and response");
+    };
+
+    let time = DateTime::parse_from_rfc3339(time)
+        .map_err(|_| "invalid time")?
+        .with_timezone(&Utc);
+    let request = unhex(request)
+        .ok_or("invalid request hex")?
+        .try_into()
+        .map_err(|_| "request has the wrong size")?;
+    let response = unhex(response).ok_or("invalid response hex")?;
+    if response.len() != RESPONSE_SIZE && response.len() != RESPONSE_SIZE_MTU {
+        return Err("response has the wrong size");
+    }
+
+    Ok(Exchange {
+        time,
+        request,
+        response,
+    })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,67 @@
+use std::{io, time::Duration};
+
+use crate::Error;
+
+pub(crate) const TRANSIENT_ERROR_KINDS: &[io::ErrorKind] = &[
+    io::ErrorKind::NotFound,
+    io::ErrorKind::ConnectionRefused,
+    io::ErrorKind::ConnectionReset,
+    io::ErrorKind::ConnectionAborted,
+    io::ErrorKind::NotConnected,
+    io::ErrorKind::BrokenPipe,
+    io::ErrorKind::UnexpectedEof,
+];
+
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Total number of attempts per query, including the first one.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: u32,
+    /// I/O error kinds that cause the client to reconnect and replay the
+    /// request.
+    pub transient: Vec<io::ErrorKind>,
+    pub retry_timeouts: bool,
+}
+
+impl RetryPolicy {
+    pub fn never() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_transient(&self, error: &Error) -> bool {
+        match error {
+            Error::Io(err) => self.transient.contains(&err.kind()),
+            Error::Timeout => self.retry_timeouts,
+            _ => false,
+        }
+    }
+
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = self
+            .multiplier
+            .checked_pow(attempt.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+
+        self.initial_backoff
+            .checked_mul(factor)
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(1),
+            multiplier: 2,
+            transient: TRANSIENT_ERROR_KINDS.to_vec(),
+            retry_timeouts: true,
+        }
+    }
+}
@@ -0,0 +1,193 @@
+//! A server for the p0f API protocol.
+//!
+//! [`P0fServer`] answers queries from any p0f API client, including the ones
+//! that ship with p0f and [`P0f`](crate::P0f) itself, with host data looked up
+//! in a [`HostStore`]. Like p0f, it serves a bounded number of connections at
+//! a time and answers the queries on each connection strictly in order.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read as _, Write as _},
+    net::IpAddr,
+    os::unix::{
+        fs::FileTypeExt as _,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::{
+    listener::Listener,
+    pool::DEFAULT_API_MAX_CONN,
+    protocol::{self, Request, ResponseLayout, REQUEST_SIZE, STATUS_BADQUERY},
+    Response,
+};
+
+/// Where a [`P0fServer`] gets its answers from. Returning `None` answers the
+/// query with `STATUS_NOMATCH`.
+pub trait HostStore: Send + Sync + 'static {
+    fn lookup(&self, address: IpAddr) -> Option<Response>;
+}
+
+impl<F> HostStore for F
+where
+    F: Fn(IpAddr) -> Option<Response> + Send + Sync + 'static,
+{
+    fn lookup(&self, address: IpAddr) -> Option<Response> {
+        self(address)
+    }
+}
+
+impl HostStore for HashMap<IpAddr, Response> {
+    fn lookup(&self, address: IpAddr) -> Option<Response> {
+        self.get(&address).cloned()
+    }
+}
+
+impl HostStore for Mutex<HashMap<IpAddr, Response>> {
+    fn lookup(&self, address: IpAddr) -> Option<Response> {
+        lock(self).get(&address).cloned()
+    }
+}
+
+impl<S: HostStore> HostStore for Arc<S> {
+    fn lookup(&self, address: IpAddr) -> Option<Response> {
+        S::lookup(self, address)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    /// Maximum number of simultaneous connections, p0f's `-S`. Connections
+    /// beyond this are accepted and closed right away, just like p0f does.
+    pub api_max_conn: usize,
+    /// [`ResponseLayout::Auto`] is served as the stock layout.
+    pub layout: ResponseLayout,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            api_max_conn: DEFAULT_API_MAX_CONN,
+            layout: ResponseLayout::default(),
+        }
+    }
+}
+
+/// Stops serving, closes all connections and removes the socket when
+/// dropped.
+pub struct P0fServer {
+    path: PathBuf,
+    shared: Arc<Shared>,
+    listener: Listener,
+}
+
+struct Shared {
+    store: Box<dyn HostStore>,
+    config: ServerConfig,
+}
+
+impl P0fServer {
+    pub fn bind<T: AsRef<Path>, S: HostStore>(path: T, store: S) -> io::Result<Self> {
+        Self::with_config(path, store, ServerConfig::default())
+    }
+
+    /// A stale socket left behind at `path` is replaced, any other file there
+    /// makes this fail.
+    pub fn with_config<T: AsRef<Path>, S: HostStore>(
+        path: T,
+        store: S,
+        config: ServerConfig,
+    ) -> io::Result<Self> {
+        if config.api_max_conn == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "api_max_conn must be at least 1",
+            ));
+        }
+
+        let path = path.as_ref().to_path_buf();
+        if fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+
+        let shared = Arc::new(Shared {
+            store: Box::new(store),
+            config: ServerConfig {
+                layout: match config.layout {
+                    ResponseLayout::Auto => ResponseLayout::Stock,
+                    layout => layout,
+                },
+                ..config
+            },
+        });
+
+        let listener = {
+            let shared = shared.clone();
+            Listener::spawn(
+                listener,
+                path.clone(),
+                shared.config.api_max_conn,
+                move |stream| serve(stream, &shared),
+            )
+        };
+
+        Ok(P0fServer {
+            path,
+            shared,
+            listener,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn config(&self) -> &ServerConfig {
+        &self.shared.config
+    }
+
+    /// Number of client connections currently being served.
+    pub fn connections(&self) -> usize {
+        self.listener.connections()
+    }
+
+    /// Number of connections closed so far for exceeding `api_max_conn`.
+    pub fn rejected(&self) -> u64 {
+        self.listener.rejected()
+    }
+}
+
+impl Drop for P0fServer {
+    fn drop(&mut self) {
+        self.listener.shutdown();
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn serve(mut stream: UnixStream, shared: &Shared) {
+    let layout = shared.config.layout;
+    let mut request = [0; REQUEST_SIZE];
+
+    while stream.read_exact(&mut request).is_ok() {
+        // p0f answers both a bad magic and an unknown address type with
+        // STATUS_BADQUERY and keeps the connection open.
+        let response = match Request::decode(&request) {
+            Ok(request) => {
+                protocol::encode_response(shared.store.lookup(request.address).as_ref(), layout)
+            }
+            Err(_) => protocol::encode_status(STATUS_BADQUERY, layout),
+        };
+
+        if stream.write_all(&response).is_err() {
+            break;
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
@@ -0,0 +1,8 @@
+//! Typed versions of the signatures p0f writes to its log and reads from
+//! `p0f.fp`.
+
+mod http;
+mod tcp;
+
+pub use http::{Header, HttpSignature, HttpVersion};
+pub use tcp::{IpVersion, PayloadClass, Quirk, TcpOption, TcpSignature, Ttl, WindowSize};
@@ -0,0 +1,238 @@
+use std::{fmt, str::FromStr};
+
+/// A `ver:horder:habsent:expsw` HTTP signature, e.g.
+/// `1:Host,User-Agent,Accept=[*/*],?Referer:Keep-Alive:Chrom`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct HttpSignature {
+    pub version: HttpVersion,
+    /// Headers in the order they have to appear in.
+    pub horder: Vec<Header>,
+    /// Names of headers that must not appear at all.
+    pub habsent: Vec<String>,
+    /// Expected `User-Agent` or `Server`, a substring of it. It is everything
+    /// after the third colon, so it may contain colons itself.
+    pub expsw: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HttpVersion {
+    /// `0`, HTTP/1.0.
+    V10,
+    /// `1`, HTTP/1.1.
+    V11,
+    /// `*`
+    Any,
+}
+
+/// A header in the `horder` of an [`HttpSignature`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Header {
+    pub name: String,
+    /// `Accept-Encoding=[gzip]`, a substring the header's value has to
+    /// contain.
+    pub value: Option<String>,
+    /// `?Referer`, a header that may be left out, but has to be in this
+    /// position if present.
+    pub optional: bool,
+}
+
+impl Header {
+    pub fn new<T: Into<String>>(name: T) -> Self {
+        Header {
+            name: name.into(),
+            value: None,
+            optional: false,
+        }
+    }
+}
+
+impl FromStr for HttpSignature {
+    type Err = &'static str;
+
+    fn from_str(sig: &str) -> Result<Self, Self::Err> {
+        let mut fields = split(sig, ':', 3).into_iter();
+        let (Some(version), Some(horder), Some(habsent), Some(expsw)) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return Err("http signature must have 4 fields");
+        };
+
+        Ok(HttpSignature {
+            version: version.parse()?,
+            horder: split(horder, ',', usize::MAX)
+                .into_iter()
+                .filter(|header| !header.is_empty())
+                .map(str::parse)
+                .collect::<Result<_, _>>()?,
+            habsent: habsent
+                .split(',')
+                .filter(|header| !header.is_empty())
+                .map(str::to_string)
+                .collect(),
+            expsw: expsw.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for HttpSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.version)?;
+        for (i, header) in self.horder.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{header}")?;
+        }
+
+        write!(f, ":{}:{}", self.habsent.join(","), self.expsw)
+    }
+}
+
+impl FromStr for HttpVersion {
+    type Err = &'static str;
+
+    fn from_str(version: &str) -> Result<Self, Self::Err> {
+        match version {
+            "0" => Ok(HttpVersion::V10),
+            "1" => Ok(HttpVersion::V11),
+            "*" => Ok(HttpVersion::Any),
+            _ => Err("http version must be 0, 1 or *"),
+        }
+    }
+}
+
+impl fmt::Display for HttpVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HttpVersion::V10 => "0",
+            HttpVersion::V11 => "1",
+            HttpVersion::Any => "*",
+        })
+    }
+}
+
+impl FromStr for Header {
+    type Err = &'static str;
+
+    fn from_str(header: &str) -> Result<Self, Self::Err> {
+        let (optional, header) = match header.strip_prefix('?') {
+            Some(header) => (true, header),
+            None => (false, header),
+        };
+
+        let (name, value) = match header.split_once('=') {
+            Some((name, value)) => {
+                let value = value
+                    .strip_prefix('[')
+                    .and_then(|value| value.strip_suffix(']'))
+                    .ok_or("header value must be in brackets")?;
+                (name, Some(value.to_string()))
+            }
+            None => (header, None),
+        };
+        if name.is_empty() {
+            return Err("empty header name");
+        }
+
+        Ok(Header {
+            name: name.to_string(),
+            value,
+            optional,
+        })
+    }
+}
+
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.optional {
+            f.write_str("?")?;
+        }
+        f.write_str(&self.name)?;
+        if let Some(value) = &self.value {
+            write!(f, "=[{value}]")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Splits at `separator`, at most `max` times, but not within the brackets
+/// of a header value, which may contain both commas and colons.
+fn split(value: &str, separator: char, max: usize) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut in_value = false;
+
+    for (i, c) in value.char_indices() {
+        match c {
+            '[' => in_value = true,
+            ']' => in_value = false,
+            c if c == separator && !in_value && parts.len() < max => {
+                parts.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for sig in [
+            "*:Host,User-Agent,Accept=[,*/*;q=],?Accept-Language=[;q=],Accept-Encoding=[gzip, deflate],?Accept-Charset=[utf-8;q=0.7,*;q=0.7],Keep-Alive=[300],Connection=[keep-alive]::Firefox/",
+            "1:Host,User-Agent,Accept=[*/*]:Connection,Accept-Encoding,Accept-Language,Accept-Charset,Keep-Alive:curl/",
+            "0:Server,Date,X-Powered-By=[PHP/5.3.2]:Keep-Alive:Apache/2.2.14 (Ubuntu)",
+            "1:Host,Accept=[text/html: level=1]::Agent: with colons",
+            "*:::",
+        ] {
+            let parsed = sig.parse::<HttpSignature>().unwrap();
+            assert_eq!(parsed.to_string(), sig);
+            assert_eq!(parsed.to_string().parse::<HttpSignature>().unwrap(), parsed);
+        }
+    }
+
+    #[test]
+    fn fields() {
+        let sig = "1:Host,?Referer,Accept=[a,b:c]:Connection,Keep-Alive:Wget/1.1:x"
+            .parse::<HttpSignature>()
+            .unwrap();
+
+        assert_eq!(sig.version, HttpVersion::V11);
+        assert_eq!(
+            sig.horder,
+            [
+                Header::new("Host"),
+                Header {
+                    optional: true,
+                    ..Header::new("Referer")
+                },
+                Header {
+                    value: Some("a,b:c".to_string()),
+                    ..Header::new("Accept")
+                },
+            ]
+        );
+        assert_eq!(sig.habsent, ["Connection", "Keep-Alive"]);
+        assert_eq!(sig.expsw, "Wget/1.1:x");
+    }
+
+    #[test]
+    fn malformed() {
+        for sig in [
+            "",
+            "1:Host:",
+            "2:Host::",
+            "1:Host,Accept=*/*::",
+            "1:Host,?::",
+        ] {
+            assert!(sig.parse::<HttpSignature>().is_err(), "{sig}");
+        }
+    }
+}
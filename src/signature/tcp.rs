@@ -0,0 +1,449 @@
+use std::{fmt, str::FromStr};
+
+/// A `ver:ittl:olen:mss:wsize,scale:olayout:quirks:pclass` TCP signature,
+/// e.g. `*:64:0:*:mss*20,10:mss,sok,ts,nop,ws:df,id+:0`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TcpSignature {
+    pub version: IpVersion,
+    pub ittl: Ttl,
+    /// Length of the IP options, in bytes.
+    pub olen: u8,
+    /// `None` for `*`.
+    pub mss: Option<u16>,
+    pub wsize: WindowSize,
+    /// Window scale, `None` for `*`.
+    pub wscale: Option<u8>,
+    pub olayout: Vec<TcpOption>,
+    pub quirks: Vec<Quirk>,
+    pub pclass: PayloadClass,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum IpVersion {
+    V4,
+    V6,
+    /// `*`
+    Any,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Ttl {
+    /// `64`, an initial TTL as written in `p0f.fp`.
+    Exact(u8),
+    /// `54+10`, the TTL as seen on the wire plus the distance p0f derived
+    /// from it, which add up to the initial TTL.
+    Distance(u8, u8),
+    /// `54+?`, a TTL for which p0f couldn't tell the distance.
+    Guessed(u8),
+    /// `54-`, a TTL p0f considers bogus, e.g. one that is higher than any
+    /// known initial TTL.
+    Bad(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum WindowSize {
+    /// `*`
+    Any,
+    /// `8192`
+    Value(u16),
+    /// `mss*20`, a multiple of the MSS.
+    Mss(u16),
+    /// `mtu*4`, a multiple of the MTU.
+    Mtu(u16),
+    /// `%8192`, any multiple of the value.
+    Mod(u16),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TcpOption {
+    /// `eol+N`, end of options followed by N bytes of padding.
+    Eol(u8),
+    Nop,
+    Mss,
+    Ws,
+    Sok,
+    Sack,
+    Ts,
+    /// `?N`, an option of unknown kind N.
+    Unknown(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Quirk {
+    /// `df`, don't fragment set.
+    Df,
+    /// `id+`, non-zero IP ID with don't fragment set.
+    NonZeroId,
+    /// `id-`, zero IP ID without don't fragment set.
+    ZeroId,
+    /// `ecn`, explicit congestion notification support.
+    Ecn,
+    /// `0+`, the "must be zero" IPv4 flag is set.
+    MustBeZero,
+    /// `flow`, non-zero IPv6 flow ID.
+    FlowId,
+    /// `seq-`, zero sequence number.
+    SeqZero,
+    /// `ack+`, non-zero ACK number without ACK set.
+    AckNonZero,
+    /// `ack-`, zero ACK number with ACK set.
+    AckZero,
+    /// `uptr+`, non-zero urgent pointer without URG set.
+    UptrNonZero,
+    /// `urgf+`, URG set.
+    Urg,
+    /// `pushf+`, PUSH set.
+    Push,
+    /// `ts1-`, zero own timestamp.
+    Ts1Zero,
+    /// `ts2+`, non-zero peer timestamp on the initial SYN.
+    Ts2NonZero,
+    /// `opt+`, non-zero data in the option padding.
+    OptNonZero,
+    /// `exws`, excessive window scaling.
+    ExcessiveWs,
+    /// `bad`, malformed options.
+    OptBad,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PayloadClass {
+    /// `0`
+    Zero,
+    /// `+`
+    NonZero,
+    /// `*`
+    Any,
+}
+
+impl FromStr for TcpSignature {
+    type Err = &'static str;
+
+    fn from_str(sig: &str) -> Result<Self, Self::Err> {
+        let fields = sig.split(':').collect::<Vec<_>>();
+        let [version, ittl, olen, mss, window, olayout, quirks, pclass] = fields[..] else {
+            return Err("tcp signature must have 8 fields");
+        };
+        let Some((wsize, wscale)) = window.split_once(',') else {
+            return Err("tcp signature window must be wsize,scale");
+        };
+
+        Ok(TcpSignature {
+            version: version.parse()?,
+            ittl: ittl.parse()?,
+            olen: olen.parse().map_err(|_| "invalid olen")?,
+            mss: any(mss, "invalid mss")?,
+            wsize: wsize.parse()?,
+            wscale: any(wscale, "invalid window scale")?,
+            olayout: list(olayout)?,
+            quirks: list(quirks)?,
+            pclass: pclass.parse()?,
+        })
+    }
+}
+
+impl fmt::Display for TcpSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}:", self.version, self.ittl, self.olen)?;
+        match self.mss {
+            Some(mss) => write!(f, "{mss}:")?,
+            None => f.write_str("*:")?,
+        }
+        write!(f, "{},", self.wsize)?;
+        match self.wscale {
+            Some(wscale) => write!(f, "{wscale}:")?,
+            None => f.write_str("*:")?,
+        }
+        write_list(f, &self.olayout)?;
+        f.write_str(":")?;
+        write_list(f, &self.quirks)?;
+
+        write!(f, ":{}", self.pclass)
+    }
+}
+
+impl FromStr for IpVersion {
+    type Err = &'static str;
+
+    fn from_str(version: &str) -> Result<Self, Self::Err> {
+        match version {
+            "4" => Ok(IpVersion::V4),
+            "6" => Ok(IpVersion::V6),
+            "*" => Ok(IpVersion::Any),
+            _ => Err("ip version must be 4, 6 or *"),
+        }
+    }
+}
+
+impl fmt::Display for IpVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            IpVersion::V4 => "4",
+            IpVersion::V6 => "6",
+            IpVersion::Any => "*",
+        })
+    }
+}
+
+impl FromStr for Ttl {
+    type Err = &'static str;
+
+    fn from_str(ttl: &str) -> Result<Self, Self::Err> {
+        let value = |ttl: &str| ttl.parse().map_err(|_| "invalid ttl");
+
+        if let Some(ttl) = ttl.strip_suffix('-') {
+            Ok(Ttl::Bad(value(ttl)?))
+        } else if let Some(ttl) = ttl.strip_suffix("+?") {
+            Ok(Ttl::Guessed(value(ttl)?))
+        } else if let Some((ttl, distance)) = ttl.split_once('+') {
+            Ok(Ttl::Distance(value(ttl)?, value(distance)?))
+        } else {
+            Ok(Ttl::Exact(value(ttl)?))
+        }
+    }
+}
+
+impl fmt::Display for Ttl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ttl::Exact(ttl) => write!(f, "{ttl}"),
+            Ttl::Distance(ttl, distance) => write!(f, "{ttl}+{distance}"),
+            Ttl::Guessed(ttl) => write!(f, "{ttl}+?"),
+            Ttl::Bad(ttl) => write!(f, "{ttl}-"),
+        }
+    }
+}
+
+impl FromStr for WindowSize {
+    type Err = &'static str;
+
+    fn from_str(wsize: &str) -> Result<Self, Self::Err> {
+        let value = |wsize: &str| wsize.parse().map_err(|_| "invalid window size");
+
+        if wsize == "*" {
+            Ok(WindowSize::Any)
+        } else if let Some(multiple) = wsize.strip_prefix("mss*") {
+            Ok(WindowSize::Mss(value(multiple)?))
+        } else if let Some(multiple) = wsize.strip_prefix("mtu*") {
+            Ok(WindowSize::Mtu(value(multiple)?))
+        } else if let Some(modulo) = wsize.strip_prefix('%') {
+            Ok(WindowSize::Mod(value(modulo)?))
+        } else {
+            Ok(WindowSize::Value(value(wsize)?))
+        }
+    }
+}
+
+impl fmt::Display for WindowSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WindowSize::Any => f.write_str("*"),
+            WindowSize::Value(wsize) => write!(f, "{wsize}"),
+            WindowSize::Mss(multiple) => write!(f, "mss*{multiple}"),
+            WindowSize::Mtu(multiple) => write!(f, "mtu*{multiple}"),
+            WindowSize::Mod(modulo) => write!(f, "%{modulo}"),
+        }
+    }
+}
+
+impl FromStr for TcpOption {
+    type Err = &'static str;
+
+    fn from_str(option: &str) -> Result<Self, Self::Err> {
+        match option {
+            "nop" => Ok(TcpOption::Nop),
+            "mss" => Ok(TcpOption::Mss),
+            "ws" => Ok(TcpOption::Ws),
+            "sok" => Ok(TcpOption::Sok),
+            "sack" => Ok(TcpOption::Sack),
+            "ts" => Ok(TcpOption::Ts),
+            _ => {
+                if let Some(padding) = option.strip_prefix("eol+") {
+                    padding
+                        .parse()
+                        .map(TcpOption::Eol)
+                        .map_err(|_| "invalid eol padding")
+                } else if let Some(kind) = option.strip_prefix('?') {
+                    kind.parse()
+                        .map(TcpOption::Unknown)
+                        .map_err(|_| "invalid option kind")
+                } else {
+                    Err("unknown tcp option")
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for TcpOption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TcpOption::Eol(padding) => write!(f, "eol+{padding}"),
+            TcpOption::Nop => f.write_str("nop"),
+            TcpOption::Mss => f.write_str("mss"),
+            TcpOption::Ws => f.write_str("ws"),
+            TcpOption::Sok => f.write_str("sok"),
+            TcpOption::Sack => f.write_str("sack"),
+            TcpOption::Ts => f.write_str("ts"),
+            TcpOption::Unknown(kind) => write!(f, "?{kind}"),
+        }
+    }
+}
+
+impl Quirk {
+    pub(crate) const ALL: [(Quirk, &'static str); 17] = [
+        (Quirk::Df, "df"),
+        (Quirk::NonZeroId, "id+"),
+        (Quirk::ZeroId, "id-"),
+        (Quirk::Ecn, "ecn"),
+        (Quirk::MustBeZero, "0+"),
+        (Quirk::FlowId, "flow"),
+        (Quirk::SeqZero, "seq-"),
+        (Quirk::AckNonZero, "ack+"),
+        (Quirk::AckZero, "ack-"),
+        (Quirk::UptrNonZero, "uptr+"),
+        (Quirk::Urg, "urgf+"),
+        (Quirk::Push, "pushf+"),
+        (Quirk::Ts1Zero, "ts1-"),
+        (Quirk::Ts2NonZero, "ts2+"),
+        (Quirk::OptNonZero, "opt+"),
+        (Quirk::ExcessiveWs, "exws"),
+        (Quirk::OptBad, "bad"),
+    ];
+}
+
+impl FromStr for Quirk {
+    type Err = &'static str;
+
+    fn from_str(quirk: &str) -> Result<Self, Self::Err> {
+        Quirk::ALL
+            .iter()
+            .find(|(_, name)| *name == quirk)
+            .map(|(quirk, _)| *quirk)
+            .ok_or("unknown quirk")
+    }
+}
+
+impl fmt::Display for Quirk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (_, name) = Quirk::ALL
+            .iter()
+            .find(|(quirk, _)| quirk == self)
+            .expect("every quirk has a name");
+
+        f.write_str(name)
+    }
+}
+
+impl FromStr for PayloadClass {
+    type Err = &'static str;
+
+    fn from_str(pclass: &str) -> Result<Self, Self::Err> {
+        match pclass {
+            "0" => Ok(PayloadClass::Zero),
+            "+" => Ok(PayloadClass::NonZero),
+            "*" => Ok(PayloadClass::Any),
+            _ => Err("payload class must be 0, + or *"),
+        }
+    }
+}
+
+impl fmt::Display for PayloadClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PayloadClass::Zero => "0",
+            PayloadClass::NonZero => "+",
+            PayloadClass::Any => "*",
+        })
+    }
+}
+
+fn any<T: FromStr>(value: &str, err: &'static str) -> Result<Option<T>, &'static str> {
+    match value {
+        "*" => Ok(None),
+        value => value.parse().map(Some).map_err(|_| err),
+    }
+}
+
+fn list<T: FromStr<Err = &'static str>>(value: &str) -> Result<Vec<T>, &'static str> {
+    if value.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    value.split(',').map(str::parse).collect()
+}
+
+fn write_list<T: fmt::Display>(f: &mut fmt::Formatter<'_>, items: &[T]) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            f.write_str(",")?;
+        }
+        write!(f, "{item}")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for sig in [
+            "*:64:0:*:mss*20,10:mss,sok,ts,nop,ws:df,id+:0",
+            "4:128:0:*:8192,8:mss,nop,ws,nop,nop,sok:df,id+:0",
+            "6:64:0:1440:%8192,0:mss,nop,nop,ts:flow:0",
+            "*:255:4:*:mtu*4,*:mss,eol+1:0+,ecn,seq-,ack+,ack-,uptr+,urgf+,pushf+:*",
+            "4:54+10:0:1460:65535,3:mss,sack,?29,nop:ts1-,ts2+,opt+,exws,bad,id-:+",
+            "4:54+?:0:*:*,*::df:0",
+            "4:200-:0:536:1024,0:mss:df:0",
+        ] {
+            let parsed = sig.parse::<TcpSignature>().unwrap();
+            assert_eq!(parsed.to_string(), sig);
+            assert_eq!(parsed.to_string().parse::<TcpSignature>().unwrap(), parsed);
+        }
+    }
+
+    #[test]
+    fn fields() {
+        let sig = "4:54+10:0:1460:mss*20,7:mss,sok,ts,nop,ws:df,id+:0"
+            .parse::<TcpSignature>()
+            .unwrap();
+
+        assert_eq!(sig.version, IpVersion::V4);
+        assert_eq!(sig.ittl, Ttl::Distance(54, 10));
+        assert_eq!(sig.mss, Some(1460));
+        assert_eq!(sig.wsize, WindowSize::Mss(20));
+        assert_eq!(sig.wscale, Some(7));
+        assert_eq!(
+            sig.olayout,
+            [
+                TcpOption::Mss,
+                TcpOption::Sok,
+                TcpOption::Ts,
+                TcpOption::Nop,
+                TcpOption::Ws
+            ]
+        );
+        assert_eq!(sig.quirks, [Quirk::Df, Quirk::NonZeroId]);
+        assert_eq!(sig.pclass, PayloadClass::Zero);
+    }
+
+    #[test]
+    fn malformed() {
+        for sig in [
+            "",
+            "4:64:0:*:mss*20,10:mss:df",
+            "4:64:0:*:mss*20,10:mss:df:0:0",
+            "5:64:0:*:mss*20,10:mss:df:0",
+            "4:x:0:*:mss*20,10:mss:df:0",
+            "4:64:0:*:mss*20:mss:df:0",
+            "4:64:0:*:mss*20,10:mss,foo:df:0",
+            "4:64:0:*:mss*20,10:mss:foo:0",
+            "4:64:0:*:mss*20,10:mss:df:1",
+        ] {
+            assert!(sig.parse::<TcpSignature>().is_err(), "{sig}");
+        }
+    }
+}
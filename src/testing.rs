@@ -0,0 +1,417 @@
+//! An in-process stand-in for the p0f API socket.
+//!
+//! [`MockP0fServer`] serves every connection from a plain thread, so it works
+//! the same for [`P0f`](crate::P0f) in sync tests and for
+//! [`AsyncP0f`](crate::AsyncP0f) under any tokio runtime.
+//!
+//! Queries can be made to fail on purpose with a [`Fault`]. Each fault maps to
+//! the [`Error`] that [`P0f::query`](crate::P0f::query) returns for it, see
+//! [`Fault::is_expected`]. That mapping assumes the client was built with
+//! [`RetryPolicy::never`](crate::RetryPolicy::never); otherwise transient
+//! faults get retried away.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, Read as _, Write as _},
+    net::IpAddr,
+    ops::Range,
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::Duration,
+};
+
+use tempfile::TempDir;
+
+use crate::{
+    listener::Listener,
+    protocol::{self, Request, ResponseLayout, REQUEST_SIZE, RESPONSE_SIZE_MTU, STATUS_BADQUERY},
+    record::{self, Exchange, RecordError},
+    Error, Response,
+};
+
+const SOCKET_NAME: &str = "p0f.sock";
+
+const MAGIC: Range<usize> = 0..4;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// Answer with a response that doesn't start with `RESPONSE_MAGIC`.
+    BadMagic,
+    /// Answer with `STATUS_BADQUERY`, whatever the query was.
+    BadQuery,
+    /// Answer with an unknown status code.
+    UnknownStatus(u32),
+    /// Send only the first bytes of the response and leave the connection
+    /// open, so the client is left waiting for the rest.
+    Truncated(usize),
+    /// Hold the response back, e.g. for longer than the client's read
+    /// timeout.
+    Delay(Duration),
+    /// Send half of the response and close the connection. If the client
+    /// wrote requests ahead, like [`P0f::query_many`](crate::P0f::query_many)
+    /// does, the connection is reset instead.
+    DropMidResponse,
+}
+
+impl Fault {
+    /// Whether `error` is what [`P0f::query`](crate::P0f::query) returns for
+    /// this fault. [`Fault::Delay`] only fails queries that have a read
+    /// timeout or deadline shorter than the delay.
+    pub fn is_expected(&self, error: &Error) -> bool {
+        match self {
+            Fault::BadMagic => matches!(error, Error::InvalidMagic),
+            Fault::BadQuery => matches!(error, Error::BadQuery),
+            Fault::UnknownStatus(status) => {
+                matches!(error, Error::UnknownStatus(value) if value == status)
+            }
+            Fault::Truncated(_) | Fault::Delay(_) => matches!(error, Error::Timeout),
+            Fault::DropMidResponse => matches!(
+                error,
+                Error::Io(err) if matches!(
+                    err.kind(),
+                    io::ErrorKind::UnexpectedEof | io::ErrorKind::ConnectionReset
+                )
+            ),
+        }
+    }
+}
+
+type FaultFn = dyn Fn(IpAddr) -> Option<Fault> + Send + Sync;
+
+#[derive(Default)]
+struct Faults {
+    queued: VecDeque<Fault>,
+    by_address: Option<Box<FaultFn>>,
+}
+
+pub(crate) enum Reply {
+    Response(Option<Box<Response>>),
+    BadQuery,
+    Raw(Vec<u8>),
+}
+
+type Handler = dyn Fn(&Request) -> Reply + Send + Sync;
+
+pub struct MockP0fServer {
+    path: PathBuf,
+    shared: Arc<Shared>,
+    _listener: Listener,
+    // Removes the socket once the server is gone.
+    _dir: TempDir,
+}
+
+struct Shared {
+    layout: ResponseLayout,
+    handler: Box<Handler>,
+    requests: Mutex<Vec<IpAddr>>,
+    faults: Mutex<Faults>,
+}
+
+impl MockP0fServer {
+    pub fn from_map(responses: HashMap<IpAddr, Option<Response>>) -> io::Result<Self> {
+        Self::from_fn(move |address| responses.get(&address).cloned().flatten())
+    }
+
+    pub fn from_fn<F>(handler: F) -> io::Result<Self>
+    where
+        F: Fn(IpAddr) -> Option<Response> + Send + Sync + 'static,
+    {
+        Self::with_layout(ResponseLayout::default(), handler)
+    }
+
+    /// Serves the responses of a recording made with
+    /// [`RecordingP0f`](crate::record::RecordingP0f), byte for byte. Repeated
+    /// requests for the same address get the recorded responses in order, and
+    /// the last one once they run out. Addresses that aren't in the recording
+    /// get `STATUS_NOMATCH`.
+    pub fn from_recording<I>(exchanges: I) -> io::Result<Self>
+    where
+        I: IntoIterator<Item = Exchange>,
+    {
+        let mut layout = ResponseLayout::default();
+        let mut responses = HashMap::<_, Vec<Vec<u8>>>::new();
+        for exchange in exchanges {
+            layout = match exchange.response.len() {
+                RESPONSE_SIZE_MTU => ResponseLayout::Mtu,
+                _ => ResponseLayout::Stock,
+            };
+            responses
+                .entry(exchange.request)
+                .or_default()
+                .push(exchange.response);
+        }
+
+        let served = Mutex::new(HashMap::<_, usize>::new());
+        Self::start(layout, move |request| {
+            let request = request.encode();
+            let Some(responses) = responses.get(&request) else {
+                return Reply::Response(None);
+            };
+
+            let mut served = lock(&served);
+            let served = served.entry(request).or_default();
+            let response = &responses[(*served).min(responses.len() - 1)];
+            *served += 1;
+
+            Reply::Raw(response.clone())
+        })
+    }
+
+    pub fn from_recording_file<T: AsRef<Path>>(path: T) -> Result<Self, RecordError> {
+        Ok(Self::from_recording(record::read_recording(path)?)?)
+    }
+
+    /// [`ResponseLayout::Auto`] is served as the stock layout.
+    pub fn with_layout<F>(layout: ResponseLayout, handler: F) -> io::Result<Self>
+    where
+        F: Fn(IpAddr) -> Option<Response> + Send + Sync + 'static,
+    {
+        Self::start(layout, move |request| {
+            Reply::Response(handler(request.address).map(Box::new))
+        })
+    }
+
+    pub(crate) fn start<F>(layout: ResponseLayout, handler: F) -> io::Result<Self>
+    where
+        F: Fn(&Request) -> Reply + Send + Sync + 'static,
+    {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(SOCKET_NAME);
+        let listener = UnixListener::bind(&path)?;
+
+        let shared = Arc::new(Shared {
+            layout: match layout {
+                ResponseLayout::Auto => ResponseLayout::Stock,
+                layout => layout,
+            },
+            handler: Box::new(handler),
+            requests: Mutex::new(Vec::new()),
+            faults: Mutex::new(Faults::default()),
+        });
+
+        let listener = {
+            let shared = shared.clone();
+            Listener::spawn(listener, path.clone(), usize::MAX, move |stream| {
+                serve(stream, &shared)
+            })
+        };
+
+        Ok(MockP0fServer {
+            path,
+            shared,
+            _listener: listener,
+            _dir: dir,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn layout(&self) -> ResponseLayout {
+        self.shared.layout
+    }
+
+    /// Every address queried so far, in the order the requests came in.
+    /// The two requests for `0.0.0.0` that open a connection made with
+    /// [`ResponseLayout::Auto`] only probe the layout, and are left out.
+    pub fn requests(&self) -> Vec<IpAddr> {
+        lock(&self.shared.requests).clone()
+    }
+
+    /// Fails the next query with `fault`. Queued faults are used up one query
+    /// at a time, in order, before [`MockP0fServer::fail_with`] is consulted.
+    /// The layout probe of [`ResponseLayout::Auto`] doesn't count as a query,
+    /// so it never fails.
+    pub fn fail_next(&self, fault: Fault) {
+        lock(&self.shared.faults).queued.push_back(fault);
+    }
+
+    /// Decides per queried address whether, and how, to fail the query. Not
+    /// consulted for the layout probe either.
+    pub fn fail_with<F>(&self, faults: F)
+    where
+        F: Fn(IpAddr) -> Option<Fault> + Send + Sync + 'static,
+    {
+        lock(&self.shared.faults).by_address = Some(Box::new(faults));
+    }
+
+    pub fn clear_faults(&self) {
+        *lock(&self.shared.faults) = Faults::default();
+    }
+}
+
+impl Shared {
+    fn next_fault(&self, address: IpAddr) -> Option<Fault> {
+        let mut faults = lock(&self.faults);
+
+        match faults.queued.pop_front() {
+            Some(fault) => Some(fault),
+            None => faults
+                .by_address
+                .as_ref()
+                .and_then(|faults| faults(address)),
+        }
+    }
+}
+
+fn serve(mut stream: UnixStream, shared: &Shared) {
+    let mut request = [0; REQUEST_SIZE];
+    // A layout probe only ever opens a connection.
+    let mut probes = 2;
+
+    while stream.read_exact(&mut request).is_ok() {
+        let (reply, fault) = match Request::decode(&request) {
+            Ok(request) if probes > 0 && protocol::is_probe(&request) => {
+                probes -= 1;
+                ((shared.handler)(&request), None)
+            }
+            Ok(request) => {
+                probes = 0;
+                lock(&shared.requests).push(request.address);
+
+                (
+                    (shared.handler)(&request),
+                    shared.next_fault(request.address),
+                )
+            }
+            Err(_) => (Reply::BadQuery, None),
+        };
+
+        let mut response = match reply {
+            Reply::Response(response) => {
+                protocol::encode_response(response.as_deref(), shared.layout)
+            }
+            Reply::BadQuery => protocol::encode_status(STATUS_BADQUERY, shared.layout),
+            Reply::Raw(response) => response,
+        };
+
+        let sent = match fault {
+            None => stream.write_all(&response),
+            Some(Fault::BadMagic) => {
+                response[MAGIC].copy_from_slice(&(!protocol::RESPONSE_MAGIC).to_ne_bytes());
+                stream.write_all(&response)
+            }
+            Some(Fault::BadQuery) => {
+                stream.write_all(&protocol::encode_status(STATUS_BADQUERY, shared.layout))
+            }
+            Some(Fault::UnknownStatus(status)) => {
+                stream.write_all(&protocol::encode_status(status, shared.layout))
+            }
+            Some(Fault::Truncated(len)) => {
+                stream.write_all(&response[..len.min(response.len() - 1)])
+            }
+            Some(Fault::Delay(delay)) => {
+                thread::sleep(delay);
+                stream.write_all(&response)
+            }
+            Some(Fault::DropMidResponse) => {
+                let _ = stream.write_all(&response[..response.len() / 2]);
+                break;
+            }
+        };
+
+        if sent.is_err() {
+            break;
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::{P0f, RetryPolicy};
+
+    const ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+
+    fn server() -> MockP0fServer {
+        MockP0fServer::from_fn(|address| Some(Response::for_address(address))).unwrap()
+    }
+
+    fn client(server: &MockP0fServer, layout: ResponseLayout) -> P0f {
+        P0f::builder(server.path())
+            .layout(layout)
+            .read_timeout(Duration::from_millis(50))
+            .retry_policy(RetryPolicy::never())
+            .connect()
+            .unwrap()
+    }
+
+    fn assert_fails(fault: Fault) {
+        let server = server();
+        let mut p0f = client(&server, ResponseLayout::Stock);
+
+        server.fail_next(fault.clone());
+        let err = p0f.query(ADDRESS).unwrap_err();
+        assert!(fault.is_expected(&err), "{fault:?}: {err:?}");
+
+        let response = p0f.query(ADDRESS).unwrap().unwrap();
+        assert_eq!(response.os_name, Some(ADDRESS.to_string()));
+    }
+
+    #[test]
+    fn bad_magic() {
+        assert_fails(Fault::BadMagic);
+    }
+
+    #[test]
+    fn bad_query() {
+        assert_fails(Fault::BadQuery);
+    }
+
+    #[test]
+    fn unknown_status() {
+        assert_fails(Fault::UnknownStatus(0x30));
+    }
+
+    #[test]
+    fn truncated() {
+        assert_fails(Fault::Truncated(10));
+    }
+
+    #[test]
+    fn delay() {
+        assert_fails(Fault::Delay(Duration::from_millis(200)));
+    }
+
+    #[test]
+    fn drop_mid_response() {
+        assert_fails(Fault::DropMidResponse);
+    }
+
+    #[test]
+    fn fail_with() {
+        let server = server();
+        let mut p0f = client(&server, ResponseLayout::Stock);
+        let other = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+
+        server.fail_with(move |address| (address == other).then_some(Fault::BadQuery));
+        assert!(p0f.query(ADDRESS).unwrap().is_some());
+        assert!(Fault::BadQuery.is_expected(&p0f.query(other).unwrap_err()));
+
+        server.clear_faults();
+        assert!(p0f.query(other).unwrap().is_some());
+    }
+
+    #[test]
+    fn probe() {
+        let server = server();
+
+        server.fail_next(Fault::BadMagic);
+        let mut p0f = client(&server, ResponseLayout::Auto);
+        assert_eq!(p0f.layout(), ResponseLayout::Stock);
+        assert!(Fault::BadMagic.is_expected(&p0f.query(ADDRESS).unwrap_err()));
+
+        let unspecified = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+        p0f.query(unspecified).unwrap();
+        assert_eq!(server.requests(), [ADDRESS, unspecified]);
+    }
+}
@@ -0,0 +1,131 @@
+//! What the uptime p0f takes from TCP timestamps says about when a host
+//! booted, and whether it booted again between two lookups.
+
+use chrono::{DateTime, Utc};
+
+use crate::Response;
+
+const SECS_PER_DAY: i64 = 86400;
+
+// p0f only believes timestamp clocks between 1 Hz and 1500 Hz, whose 32 bits
+// wrap around after 49710 and 33 days respectively.
+const MIN_WRAP_DAYS: u64 = 33;
+const MAX_WRAP_DAYS: u64 = 49710;
+
+// Uptimes come in whole minutes, and timestamp clocks run a little off, so
+// two measurements of the same boot never quite agree.
+const MIN_TOLERANCE: i64 = 5 * 60;
+const TOLERANCE_PERCENT: i64 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImplausibleUptime {
+    /// `up_mod_days` is outside of what a clock p0f accepts can give.
+    ClockRate,
+    /// `uptime_min` is longer than `up_mod_days`, which it is the remainder
+    /// of.
+    ExceedsWrap,
+    /// `uptime_min` reaches back before 1970.
+    BeforeEpoch,
+}
+
+/// A change of uptime found by [`Response::reboot_between`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Reboot {
+    /// When the host booted according to the earlier response.
+    pub previous_boot: DateTime<Utc>,
+    /// When it booted according to the later one.
+    pub boot: DateTime<Utc>,
+    /// The host was seen after `boot` with the older uptime still, so the
+    /// two can't be the same host rebooting. Several hosts behind one
+    /// address, like behind a load balancer, make for this.
+    pub overlapping: bool,
+}
+
+impl Response {
+    /// When the host booted, going by the uptime p0f measured and taking
+    /// `last_seen` as when it did. It may have been any multiple of
+    /// `up_mod_days` earlier, see [`Response::boot_times`].
+    pub fn boot_time(&self) -> Option<DateTime<Utc>> {
+        let uptime = self.uptime_min?.as_secs() as i64;
+
+        DateTime::from_timestamp(self.last_seen.timestamp() - uptime, 0)
+    }
+
+    /// Every time the host may have booted, latest first, as the timestamp
+    /// clock may have wrapped around any number of times since. Earlier
+    /// ones are left out once they would be before 1970.
+    pub fn boot_times(&self) -> impl Iterator<Item = DateTime<Utc>> {
+        let wrap = self.up_mod_days.as_secs() as i64;
+        let latest = self.boot_time();
+
+        std::iter::successors(latest, move |boot| match wrap {
+            0 => None,
+            wrap => DateTime::from_timestamp(boot.timestamp() - wrap, 0)
+                .filter(|boot| boot.timestamp() >= 0),
+        })
+    }
+
+    /// What is wrong with the uptime, if anything. p0f passes on whatever
+    /// the timestamps of a host give, including those of stacks that
+    /// randomize them.
+    pub fn implausible_uptime(&self) -> Option<ImplausibleUptime> {
+        let uptime = self.uptime_min?;
+        let wrap_days = self.up_mod_days.as_secs() / SECS_PER_DAY as u64;
+
+        if !(MIN_WRAP_DAYS..=MAX_WRAP_DAYS).contains(&wrap_days) {
+            Some(ImplausibleUptime::ClockRate)
+        } else if uptime > self.up_mod_days {
+            Some(ImplausibleUptime::ExceedsWrap)
+        } else if uptime.as_secs() as i64 > self.last_seen.timestamp() {
+            Some(ImplausibleUptime::BeforeEpoch)
+        } else {
+            None
+        }
+    }
+
+    /// Compares the uptimes of two responses for the same address, in
+    /// either order, and returns what changed if the host can't have been
+    /// up all along. The uptime of the later one has to have grown by about
+    /// the time between them, or by that less some number of wraparounds.
+    ///
+    /// p0f only measures the uptime off a SYN with timestamps, so an uptime
+    /// that didn't change at all is taken to not have been measured again.
+    pub fn reboot_between(&self, other: &Response) -> Option<Reboot> {
+        let (earlier, later) = match self.last_seen <= other.last_seen {
+            true => (self, other),
+            false => (other, self),
+        };
+        let (before, after) = (earlier.uptime_min?, later.uptime_min?);
+        if before == after {
+            return None;
+        }
+
+        let elapsed = later.last_seen.timestamp() - earlier.last_seen.timestamp();
+        let tolerance = MIN_TOLERANCE.max(elapsed * TOLERANCE_PERCENT / 100);
+        // How much less the later uptime is than it would be without a reboot.
+        let missing = before.as_secs() as i64 + elapsed - after.as_secs() as i64;
+        if missing.abs() <= tolerance {
+            return None;
+        }
+
+        // up_mod_days is rounded down to whole days, so each wraparound takes
+        // up to a day more than it says.
+        let wrap = later.up_mod_days.as_secs() as i64;
+        if wrap > 0 && missing > 0 {
+            let wraps = (missing + tolerance) / wrap;
+            if wraps > 0
+                && missing >= wraps * wrap - tolerance
+                && missing <= wraps * (wrap + SECS_PER_DAY) + tolerance
+            {
+                return None;
+            }
+        }
+
+        let boot = later.boot_time()?;
+        Some(Reboot {
+            previous_boot: earlier.boot_time()?,
+            boot,
+            overlapping: boot.timestamp() < earlier.last_seen.timestamp() - tolerance,
+        })
+    }
+}
@@ -0,0 +1,257 @@
+//! `User-Agent` parsing, and p0f's `bad_sw` check of one against what the
+//! fingerprints say, for when the `User-Agent` comes from somewhere p0f can't
+//! see, like behind TLS.
+
+use crate::{fpdb::Database, os::OsFamily, BadSw, Response};
+
+// Looked for in this order, so that e.g. Android wins over the Linux it
+// also mentions, and iOS over the `like Mac OS X` in its `User-Agent`.
+const OS_TOKENS: [(&str, OsFamily); 19] = [
+    ("Windows Phone", OsFamily::Windows),
+    ("Android", OsFamily::Android),
+    ("CrOS", OsFamily::ChromeOs),
+    ("iPhone", OsFamily::Ios),
+    ("iPad", OsFamily::Ios),
+    ("iPod", OsFamily::Ios),
+    ("BlackBerry", OsFamily::BlackBerry),
+    ("BB10", OsFamily::BlackBerry),
+    ("Nintendo", OsFamily::Nintendo),
+    ("Windows", OsFamily::Windows),
+    ("Win64", OsFamily::Windows),
+    ("Macintosh", OsFamily::MacOs),
+    ("Mac OS X", OsFamily::MacOs),
+    ("FreeBSD", OsFamily::FreeBsd),
+    ("OpenBSD", OsFamily::OpenBsd),
+    ("NetBSD", OsFamily::NetBsd),
+    ("SunOS", OsFamily::Solaris),
+    ("Linux", OsFamily::Linux),
+    ("Ubuntu", OsFamily::Linux),
+];
+
+/// What a `User-Agent` says about the system it comes from.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UserAgent {
+    pub os: Option<OsFamily>,
+    /// E.g. `10.15.7` for macOS or `10.0` for Windows NT 10.0, as far as the
+    /// `User-Agent` gives it away.
+    pub os_version: Option<String>,
+}
+
+impl UserAgent {
+    /// Knows more families and versions than the `ua_os` of `p0f.fp`, which
+    /// is what [`check_user_agent`] goes by instead.
+    pub fn parse(user_agent: &str) -> Self {
+        let Some(os) = OS_TOKENS
+            .iter()
+            .find(|(token, _)| user_agent.contains(token))
+            .map(|(_, family)| *family)
+        else {
+            return UserAgent::default();
+        };
+
+        let os_version = match os {
+            OsFamily::Windows => version_after(user_agent, "Windows NT ")
+                .or_else(|| version_after(user_agent, "Windows Phone ")),
+            OsFamily::Android => version_after(user_agent, "Android "),
+            OsFamily::Ios => version_after(user_agent, " OS "),
+            OsFamily::MacOs => version_after(user_agent, "Mac OS X "),
+            _ => None,
+        };
+
+        UserAgent {
+            os: Some(os),
+            os_version,
+        }
+    }
+}
+
+/// The outcome of [`check_user_agent`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UaVerdict {
+    /// The worse of what `reasons` amount to, `None` without any.
+    pub bad_sw: Option<BadSw>,
+    pub reasons: Vec<Mismatch>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Mismatch {
+    /// The `User-Agent` claims another OS than the TCP fingerprint found, as
+    /// happens behind a proxy or NAT. Makes for [`BadSw::OsDifference`].
+    Os {
+        claimed: OsFamily,
+        detected: OsFamily,
+    },
+    /// The `User-Agent` doesn't name the software the HTTP fingerprint found.
+    /// Makes for [`BadSw::OutrightMismatch`].
+    Software { detected: String },
+}
+
+/// Checks a `User-Agent` against what p0f found for the host it came from,
+/// the way p0f fills `bad_sw` with `db` loaded: the OS is the first of its
+/// `ua_os` the `User-Agent` mentions, and the software has to show up the
+/// way one of its `[http:request]` signatures for it expects.
+///
+/// Only OS families that run on different TCP/IP stacks count as different,
+/// since p0f can't tell e.g. Android from Linux by their SYNs alone. Hosts
+/// p0f has no OS or HTTP software for, and `User-Agent`s that name no OS,
+/// can't contradict anything.
+pub fn check_user_agent(db: &Database, response: &Response, user_agent: &str) -> UaVerdict {
+    let mut verdict = UaVerdict::default();
+
+    if let Some(name) = &response.http_name {
+        if !names_software(db, user_agent, name) {
+            verdict.reasons.push(Mismatch::Software {
+                detected: name.clone(),
+            });
+        }
+    }
+
+    let detected = response
+        .os_name
+        .as_deref()
+        .and_then(|name| OsFamily::from_p0f(name, response.os_flavor.as_deref()));
+    let claimed = db
+        .ua_os
+        .iter()
+        .find(|os| user_agent.contains(os.ua()))
+        .and_then(|os| OsFamily::from_p0f(&os.name, None));
+    if let (Some(claimed), Some(detected)) = (claimed, detected) {
+        if claimed.kernel() != detected.kernel() {
+            verdict.reasons.push(Mismatch::Os { claimed, detected });
+        }
+    }
+
+    verdict.bad_sw = verdict
+        .reasons
+        .iter()
+        .map(|reason| match reason {
+            Mismatch::Software { .. } => BadSw::OutrightMismatch,
+            Mismatch::Os { .. } => BadSw::OsDifference,
+        })
+        .max_by_key(|bad_sw| matches!(bad_sw, BadSw::OutrightMismatch));
+
+    verdict
+}
+
+/// Software the database has no signatures for has to be named outright.
+fn names_software(db: &Database, user_agent: &str, name: &str) -> bool {
+    let mut expected = db
+        .http_request
+        .iter()
+        .filter(|entry| entry.label.name == name)
+        .flat_map(|entry| &entry.sigs)
+        .map(|sig| sig.expsw.as_str())
+        .peekable();
+
+    match expected.peek() {
+        Some(_) => expected.any(|expsw| user_agent.contains(expsw)),
+        None => user_agent.contains(name),
+    }
+}
+
+/// The version number right after `prefix`, with the underscores Apple
+/// uses turned into dots.
+fn version_after(user_agent: &str, prefix: &str) -> Option<String> {
+    let (_, rest) = user_agent.split_once(prefix)?;
+    let version = rest
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || matches!(c, '.' | '_'))
+        .map(|c| if c == '_' { '.' } else { c })
+        .collect::<String>();
+    let version = version.trim_end_matches('.');
+
+    (!version.is_empty()).then(|| version.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::*;
+
+    const STOCK: &str = include_str!("../tests/data/p0f.fp");
+
+    fn response(os_name: &str, http_name: &str) -> Response {
+        Response {
+            os_name: Some(os_name.to_string()),
+            os_flavor: None,
+            http_name: Some(http_name.to_string()),
+            ..Response::for_address(IpAddr::V4(Ipv4Addr::LOCALHOST))
+        }
+    }
+
+    #[test]
+    fn stock() {
+        let db = Database::parse(STOCK).unwrap();
+        let check = |response: &Response, user_agent| check_user_agent(&db, response, user_agent);
+
+        let firefox = response("Linux", "Firefox");
+        let user_agent = "Mozilla/5.0 (X11; Linux x86_64; rv:120.0) Gecko/20100101 Firefox/120.0";
+        assert_eq!(check(&firefox, user_agent), UaVerdict::default());
+        // Android is Linux as far as `ua_os` goes.
+        let user_agent = "Mozilla/5.0 (Linux; Android 13) Gecko/20100101 Firefox/120.0";
+        assert_eq!(check(&firefox, user_agent), UaVerdict::default());
+
+        let verdict = check(&firefox, "Mozilla/5.0 (Windows NT 10.0) Chrome/120.0");
+        assert_eq!(verdict.bad_sw, Some(BadSw::OutrightMismatch));
+        assert_eq!(
+            verdict.reasons,
+            [
+                Mismatch::Software {
+                    detected: "Firefox".to_string()
+                },
+                Mismatch::Os {
+                    claimed: OsFamily::Windows,
+                    detected: OsFamily::Linux
+                },
+            ]
+        );
+
+        // MSIE is known by `(compatible; MSIE`, not just `MSIE`.
+        let msie = response("Windows", "MSIE");
+        let verdict = check(&msie, "Mozilla/4.0 (Windows NT 6.1) MSIE 8.0");
+        assert_eq!(verdict.bad_sw, Some(BadSw::OutrightMismatch));
+        let verdict = check(&msie, "Mozilla/4.0 (compatible; MSIE 8.0; Windows NT 6.1)");
+        assert_eq!(verdict, UaVerdict::default());
+
+        // iOS only by the `iPhone` `ua_os` looks for.
+        let safari = response("Linux", "Safari");
+        let user_agent = "Mozilla/5.0 (iPhone; CPU iPhone OS 16_1 like Mac OS X) \
+                          AppleWebKit/605.1.15 (KHTML, like Gecko) Mobile/15E148";
+        let verdict = check(&safari, user_agent);
+        assert_eq!(verdict.bad_sw, Some(BadSw::OsDifference));
+        assert_eq!(
+            verdict.reasons,
+            [Mismatch::Os {
+                claimed: OsFamily::Ios,
+                detected: OsFamily::Linux
+            }]
+        );
+    }
+
+    #[test]
+    fn custom() {
+        let db = "[http:request]\n\
+                  ua_os = Windows=[Win64]\n\
+                  label = s:!:Curl:8.x\n\
+                  sig = *:Host,User-Agent,Accept::curl/\n";
+        let db = Database::parse(db).unwrap();
+
+        let curl = response("Linux", "Curl");
+        assert_eq!(
+            check_user_agent(&db, &curl, "curl/8.0 (Linux)"),
+            UaVerdict::default()
+        );
+        let verdict = check_user_agent(&db, &curl, "Curl/8.0 (Win64)");
+        assert_eq!(verdict.bad_sw, Some(BadSw::OutrightMismatch));
+        assert_eq!(verdict.reasons.len(), 2);
+        // Nothing in `ua_os` looks for `Windows` itself.
+        let verdict = check_user_agent(&db, &response("Linux", "Wget"), "wget (Windows)");
+        assert_eq!(
+            verdict.reasons,
+            [Mismatch::Software {
+                detected: "Wget".to_string()
+            }]
+        );
+    }
+}